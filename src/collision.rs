@@ -1,31 +1,372 @@
-use std::path::Path;
+use std::{collections::HashMap, path::Path};
 
 use glam::I64Vec3;
-use log::warn;
+use log::{info, warn};
 use tobj::LoadOptions;
 
-use crate::{psx_structs::{CollModelPSX, CollVertexPSX, NavGraphNode}, renderer::Renderer};
+use crate::{
+    debug_obj::write_debug_obj,
+    decimate::decimate,
+    error::{Error, Result},
+    helpers::{position_to_psx_rounded, psx_to_position},
+    psx_structs::{CollModelPSX, CollVertexPSX},
+    nav_graph::{build_nav_graphs, NavLinkHelper},
+    navmesh::build_navmesh,
+    weld::{weld, WeldReport},
+};
+
+#[derive(Clone)]
+pub struct CollisionSettings {
+    /// Use the CPU rasterizer for line-of-sight checks, even if OpenGL is available
+    pub software_renderer: bool,
+    /// Terrain IDs by material name. These take priority over `psx_terrain` parameters in the MTL file
+    pub terrain_ids: HashMap<String, u8>,
+    /// Algorithm used to split the collision BVH
+    pub bvh_builder: BvhBuilder,
+    /// Nodes with at most this many primitives become leaves
+    pub max_leaf_size: usize,
+    /// Maximum depth of the collision BVH, where the root is at depth 0
+    pub max_bvh_depth: usize,
+    /// Number of nodes the runtime's BVH traversal stack can hold. The tree depth is limited further if needed so traversal never overflows it
+    pub bvh_stack_size: usize,
+    /// Always store 32-bit indices in the FCOL file. They are used automatically when 16-bit indices don't fit
+    pub wide_indices: bool,
+    /// Also store the plane and edge data of every triangle in the FCOL file, so the runtime doesn't have to calculate it for every test
+    pub precomputed_planes: bool,
+    /// Split convex quads and other polygons into triangles, instead of skipping them with a warning. Concave polygons are always skipped
+    pub triangulate_polygons: bool,
+    /// Vertices closer together than this on every axis are merged, in OBJ units. Exact duplicates are always merged
+    pub weld_tolerance: f32,
+    /// Simplify the collision mesh, as long as no vertex moves further than this from the original surfaces, in OBJ units.
+    /// Flat regions are merged even at 0. Nav graph nodes are placed on the remaining floor triangles, so the graph gets sparser too
+    pub decimate: Option<f32>,
+    /// Steepest slope that still counts as floor, in degrees. Surfaces facing down at the same angle count as ceiling
+    pub slope_limit: f32,
+    /// Which navigation data to generate for the walkable surfaces
+    pub nav_output: NavOutput,
+    /// A navigation graph is built for each of these agents
+    pub nav_agents: Vec<NavAgent>,
+    /// Number of threads for the nav graph's line-of-sight checks with the software renderer, 0 uses every core
+    pub threads: usize,
+    /// Also write the triangles, BVH and nav graphs to this OBJ file, to check them in a 3D editor
+    pub debug_obj: Option<String>,
+}
+
+impl Default for CollisionSettings {
+    fn default() -> Self {
+        Self {
+            software_renderer: false,
+            terrain_ids: HashMap::new(),
+            bvh_builder: BvhBuilder::default(),
+            max_leaf_size: 2,
+            max_bvh_depth: 31,
+            bvh_stack_size: 32,
+            wide_indices: false,
+            precomputed_planes: false,
+            triangulate_polygons: false,
+            weld_tolerance: 0.0,
+            decimate: None,
+            slope_limit: 60.0,
+            nav_output: NavOutput::default(),
+            nav_agents: vec![NavAgent::default()],
+            threads: 0,
+            debug_obj: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum BvhBuilder {
+    /// Split the longest axis at the average vertex position
+    #[default]
+    Mean,
+    /// Binned surface area heuristic, slower to build but cheaper to traverse
+    Sah,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum NavOutput {
+    /// A node in the center of every floor triangle, linked to the closest nodes nearby. One graph per agent
+    #[default]
+    Graph,
+    /// Convex polygons merged from the floor triangles, linked through their shared edges
+    Navmesh,
+    /// Both the graph and the navmesh
+    Both,
+}
+
+/// The size of an agent a navigation graph is built for, and how its nodes get linked. Distances are in OBJ units
+#[derive(Clone, Debug, PartialEq)]
+pub struct NavAgent {
+    /// Height of the agent's eyes above the floor, where the line of sight between two nodes is checked
+    pub height: f32,
+    /// Half the width of the space the agent needs around the line of sight
+    pub radius: f32,
+    /// Maximum number of links per node
+    pub max_neighbors: usize,
+    /// Nodes further apart than this are never linked
+    pub max_link_distance: f32,
+    /// Add the reverse of every walk or jump link that only goes one way. Nodes can end up with more than `max_neighbors` links
+    pub bidirectional: bool,
+    /// Highest step the agent can walk up or down. If the agent can drop or jump, walk links need floor within this height all the way
+    pub step_height: f32,
+    /// Deepest the agent can drop down from a ledge, 0 disables drop links
+    pub max_drop: f32,
+    /// Widest gap the agent can jump over, 0 disables jump links
+    pub max_jump_distance: f32,
+}
+
+impl Default for NavAgent {
+    fn default() -> Self {
+        Self {
+            height: 0.0625,
+            radius: 0.0234375,
+            max_neighbors: 4,
+            max_link_distance: 1.0,
+            bidirectional: false,
+            step_height: 0.0625,
+            max_drop: 0.0,
+            max_jump_distance: 0.0,
+        }
+    }
+}
+
+/// Parses a comma separated list of `key=value` pairs, like `height=1.5,radius=0.25,neighbors=8,distance=2,bidirectional=true`.
+/// The step height, drop and jump distance use the keys `step`, `drop` and `jump`.
+/// Keys that are left out keep their default value
+impl std::str::FromStr for NavAgent {
+    type Err = String;
+
+    fn from_str(text: &str) -> std::result::Result<Self, Self::Err> {
+        let mut agent = NavAgent::default();
+        for pair in text.split(',') {
+            let Some((key, value)) = pair.split_once('=') else {
+                return Err(format!("expected key=value, found \"{pair}\""));
+            };
+            let (key, value) = (key.trim(), value.trim());
+            let distance = || match value.parse::<f32>() {
+                Ok(value) if value.is_finite() && value >= 0.0 => Ok(value),
+                _ => Err(format!("{key} should be a positive number, not \"{value}\"")),
+            };
+            match key {
+                "height" => agent.height = distance()?,
+                "radius" => agent.radius = distance()?,
+                "distance" => agent.max_link_distance = distance()?,
+                "step" => agent.step_height = distance()?,
+                "drop" => agent.max_drop = distance()?,
+                "jump" => agent.max_jump_distance = distance()?,
+                "neighbors" => {
+                    agent.max_neighbors = match value.parse::<u16>() {
+                        Ok(value) if value > 0 => value as usize,
+                        _ => return Err(format!("neighbors should be a number from 1 to 65535, not \"{value}\"")),
+                    }
+                }
+                "bidirectional" => {
+                    agent.bidirectional = value.parse::<bool>().map_err(|_| format!("bidirectional should be true or false, not \"{value}\""))?
+                }
+                _ => return Err(format!("unknown key \"{key}\", expected height, radius, neighbors, distance, bidirectional, step, drop or jump")),
+            }
+        }
+        Ok(agent)
+    }
+}
 
-pub fn obj2col(input_obj: String, output_col: String) {
-    let (models, _materials) = tobj::load_obj(
+pub fn obj2col(input_obj: String, output_col: String, settings: &CollisionSettings) -> Result<()> {
+    let (models, materials) = tobj::load_obj(
         input_obj,
         &LoadOptions {
             single_index: true,
             ..Default::default()
         },
-    )
-    .expect("Failed to OBJ load file");
+    )?;
+
+    // A missing MTL file is fine for collision meshes, every triangle just gets terrain ID 0
+    let materials = materials.unwrap_or_else(|err| {
+        warn!("failed to load materials ({err}), all triangles will use terrain ID 0");
+        Vec::new()
+    });
+
+    let collision_model_psx = convert_collision(&models, &materials, settings)?;
+    if let Some(debug_obj) = &settings.debug_obj {
+        write_debug_obj(&collision_model_psx, Path::new(debug_obj))?;
+    }
+    collision_model_psx.save(Path::new(&output_col))
+}
+
+/// Loads a terrain ID mapping file. Each line contains a material name followed by its terrain ID, and lines starting with # are ignored
+pub fn load_terrain_map(path: &Path) -> Result<HashMap<String, u8>> {
+    let text = std::fs::read_to_string(path)?;
+    let mut terrain_ids = HashMap::new();
+    for (line_index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
 
+        let invalid = |reason: &str| Error::InvalidData {
+            what: "terrain map",
+            reason: format!("line {}: {reason}", line_index + 1),
+        };
+        let Some((name, id)) = line.rsplit_once(char::is_whitespace) else {
+            return Err(invalid("expected a material name followed by a terrain ID"));
+        };
+        let id = id.parse::<u8>().map_err(|_| invalid(&format!("terrain ID \"{id}\" should be a number from 0 to 255")))?;
+        terrain_ids.insert(name.trim().to_string(), id);
+    }
+    Ok(terrain_ids)
+}
+
+/// Finds the terrain ID of a material, first in the mapping from the settings, then in its `psx_terrain` parameter
+fn material_terrain_id(material: &tobj::Material, settings: &CollisionSettings) -> Result<u8> {
+    if let Some(id) = settings.terrain_ids.get(&material.name) {
+        return Ok(*id);
+    }
+    if let Some(value) = material.unknown_param.get("psx_terrain") {
+        return value.trim().parse::<u8>().map_err(|_| Error::InvalidMaterial {
+            material: material.name.clone(),
+            reason: format!("psx_terrain should be a number from 0 to 255, not \"{value}\""),
+        });
+    }
+    if !settings.terrain_ids.is_empty() {
+        warn!("material {} is not in the terrain map, using terrain ID 0", material.name);
+    }
+    Ok(0)
+}
+
+/// Objects with a name starting with this aren't collision, but links for the nav graph, like `NAVLINK_LADDER_01`
+const NAV_LINK_PREFIX: &str = "NAVLINK_";
+
+/// Reads a nav graph link helper object. The link goes from the first vertex of the object to the last one, so a single line works
+fn nav_link_helper(model: &tobj::Model, link_type: &str) -> Result<NavLinkHelper> {
+    let invalid = |reason: String| Error::InvalidData {
+        what: "navigation link helper",
+        reason: format!("{}: {reason}", model.name),
+    };
+    let link_type = link_type.split('_').next().unwrap_or_default();
+    let link_type = match link_type.to_ascii_uppercase().as_str() {
+        "WALK" => NavLinkType::Walk,
+        "DROP" => NavLinkType::Drop,
+        "JUMP" => NavLinkType::Jump,
+        "LADDER" => NavLinkType::Ladder,
+        _ => return Err(invalid(format!("unknown link type \"{link_type}\", expected WALK, DROP, JUMP or LADDER"))),
+    };
+    let (Some(&first), Some(&last)) = (model.mesh.indices.first(), model.mesh.indices.last()) else {
+        return Err(invalid("the object has no vertices".to_string()));
+    };
+    Ok(NavLinkHelper {
+        name: model.name.clone(),
+        from: position_to_psx_rounded(&model.mesh.positions, first as usize, &model.name)?,
+        to: position_to_psx_rounded(&model.mesh.positions, last as usize, &model.name)?,
+        link_type,
+    })
+}
+
+/// Fits a collision shape around the vertices of an object
+type ShapeFit = fn(&[glam::IVec3]) -> CollShape;
+
+/// Objects with a name starting with one of these become a single shape fitted around their vertices, like `BOX_CRATE_01`, instead of triangles
+const SHAPE_PREFIXES: [(&str, ShapeFit); 3] = [
+    ("BOX_", CollShape::fit_box),
+    ("SPHERE_", CollShape::fit_sphere),
+    ("CAPSULE_", CollShape::fit_capsule),
+];
+
+/// Converts a PS1 position to the space the collision triangles and shapes are stored in
+fn collision_position([x, y, z]: [i16; 3]) -> glam::IVec3 {
+    glam::IVec3::new(x as i32, y as i32, z as i32) * -COL_SCALE
+}
+
+/// Whether an object is a nav link helper, trigger or marker, which are only meant for the collision model
+pub fn is_helper_object(name: &str) -> bool {
+    name.starts_with(NAV_LINK_PREFIX) || GAMEPLAY_OBJECT_PREFIXES.iter().any(|(prefix, _)| name.starts_with(prefix))
+}
+
+/// Objects with a name starting with one of these aren't solid, but are stored by name for the game to look up, like `TRIGGER_LEVEL_EXIT`
+const GAMEPLAY_OBJECT_PREFIXES: [(&str, GameplayObjectKind); 2] = [
+    ("TRIGGER_", GameplayObjectKind::Trigger),
+    ("MARKER_", GameplayObjectKind::Marker),
+];
+
+/// Reads a trigger volume or marker. Only the bounds of its vertices are kept, so any mesh works
+fn gameplay_object(model: &tobj::Model, kind: GameplayObjectKind) -> Result<GameplayObjectPSX> {
+    let mut points = vec![];
+    for index in 0..model.mesh.positions.len() / 3 {
+        points.push(collision_position(position_to_psx_rounded(&model.mesh.positions, index, &model.name)?));
+    }
+    if points.is_empty() {
+        return Err(Error::InvalidData {
+            what: "gameplay object",
+            reason: format!("{} has no vertices", model.name),
+        });
+    }
+    Ok(GameplayObjectPSX {
+        kind,
+        name: model.name.clone(),
+        bounds: point_bounds(&points),
+    })
+}
+
+/// Whether a fan of triangles from the first vertex covers a polygon exactly, which is the case when every corner turns the same way
+fn is_convex_polygon(polygon: &[CollVertexPSX]) -> bool {
+    let positions: Vec<glam::DVec3> = polygon
+        .iter()
+        .map(|vert| glam::DVec3::new(vert.pos_x as f64, vert.pos_y as f64, vert.pos_z as f64))
+        .collect();
+
+    // Newell's method gives the polygon's normal, even if some of its vertices are in a line
+    let n = positions.len();
+    let normal: glam::DVec3 = (0..n).map(|i| positions[i].cross(positions[(i + 1) % n])).sum();
+    (0..n).all(|i| {
+        let [a, b, c] = [i, i + 1, i + 2].map(|j| positions[j % n]);
+        (b - a).cross(c - b).dot(normal) >= 0.0
+    })
+}
+
+/// Converts loaded OBJ data into a collision model, including its BVH and navigation graph
+pub fn convert_collision(
+    models: &[tobj::Model],
+    materials: &[tobj::Material],
+    settings: &CollisionSettings,
+) -> Result<CollModelPSX> {
     let mut triangles = Vec::<CollVertexPSX>::new();
+    let mut weld_report = WeldReport::default();
+
+    let mut material_terrain_ids = vec![];
+    for material in materials {
+        material_terrain_ids.push(material_terrain_id(material, settings)?);
+    }
 
     // Loop over every mesh in the model. We want to combine them all.
-    for model in &models {
+    let mut nav_link_helpers = vec![];
+    let mut shapes = vec![];
+    let mut shape_triangles = Vec::<CollVertexPSX>::new();
+    let mut gameplay_objects = vec![];
+    for model in models {
+        if let Some(link_type) = model.name.strip_prefix(NAV_LINK_PREFIX) {
+            nav_link_helpers.push(nav_link_helper(model, link_type)?);
+            continue;
+        }
+        if let Some((_, kind)) = GAMEPLAY_OBJECT_PREFIXES.iter().find(|(prefix, _)| model.name.starts_with(prefix)) {
+            gameplay_objects.push(gameplay_object(model, *kind)?);
+            continue;
+        }
+        let fit_shape = SHAPE_PREFIXES
+            .iter()
+            .find(|(prefix, _)| model.name.starts_with(prefix))
+            .map(|(_, fit_shape)| fit_shape);
+        let mut shape_points = vec![];
+
         let mut curr_index = 0;
+        let terrain_id = model
+            .mesh
+            .material_id
+            .and_then(|id| material_terrain_ids.get(id))
+            .copied()
+            .unwrap_or(0);
 
         let face_arities = match model.mesh.face_arities.is_empty() {
             false => model.mesh.face_arities.clone(),
-            true => std::iter::repeat(3)
-                .take(model.mesh.indices.len() / 3)
+            true => std::iter::repeat_n(3, model.mesh.indices.len() / 3)
                 .collect(),
         };
 
@@ -33,125 +374,142 @@ pub fn obj2col(input_obj: String, output_col: String) {
             let mut curr_primitive = Vec::<CollVertexPSX>::new();
             for in_face_index in curr_index as usize..(curr_index + arity) as usize {
                 let index = model.mesh.indices[in_face_index] as usize;
+                let [pos_x, pos_y, pos_z] = position_to_psx_rounded(&model.mesh.positions, index, &model.name)?;
+                let quantization_error = glam::Vec3::from_slice(&model.mesh.positions[index * 3..])
+                    .distance(psx_to_position([pos_x, pos_y, pos_z]).into());
+                weld_report.max_quantization_error = weld_report.max_quantization_error.max(quantization_error);
                 let vert = CollVertexPSX {
-                    pos_x: (model.mesh.positions[index * 3 + 0] * -1024.0).clamp(-32768.0, 32767.0)
-                        as i16,
-                    pos_y: (model.mesh.positions[index * 3 + 1] * -1024.0).clamp(-32768.0, 32767.0)
-                        as i16,
-                    pos_z: (model.mesh.positions[index * 3 + 2] * 1024.0).clamp(-32768.0, 32767.0)
-                        as i16,
-                    _terrain_id: 0,
+                    pos_x,
+                    pos_y,
+                    pos_z,
+                    terrain_id: terrain_id as u16,
                 };
                 curr_primitive.push(vert);
             }
-            match arity {
-                3 => {
-                    for i in [0, 2, 1] {
-                        triangles.push(curr_primitive[i]);
-                    }
-                },
-                _ => warn!("found polygon with more than 3 vertices! make sure the collision mesh only contains triangles."),
-            };
-
             curr_index += arity;
-        }
-    }
-
-    let bvh = CollBvh::construct(&triangles);
-
-    let mut nav_graph_nodes = Vec::<NavGraphNode>::new();
-    for (center, primitive) in bvh.centers.iter().zip(&bvh.primitives) {
-        if primitive.normal.y < 2048 {
-            continue;
-        }
-
-        let center_x16 = (center.x / 4096) as i16;
-        let center_y16 = (center.y / 4096) as i16;
-        let center_z16 = (center.z / 4096) as i16;
-
-        let value = NavGraphNode {
-            pos_x: center_x16,
-            pos_y: center_y16,
-            pos_z: center_z16,
-            neighbors: [0, 0, 0, 0], // We fill this in below
-        };
-        nav_graph_nodes.push(value);
-    }
-
-    let mut renderer = Renderer::new();
-
-    let mut triangles_without_floor = vec![];
-    for triangle in triangles.chunks(3) {
-        let v0 = glam::vec3(triangle[0].pos_x as f32, triangle[0].pos_y as f32, triangle[0].pos_z as f32);
-        let v1 = glam::vec3(triangle[1].pos_x as f32, triangle[1].pos_y as f32, triangle[1].pos_z as f32);
-        let v2 = glam::vec3(triangle[2].pos_x as f32, triangle[2].pos_y as f32, triangle[2].pos_z as f32);
-        let edge_0_2 = v2 - v0;
-        let edge_0_1 = v1 - v0;
-        let normal = edge_0_2.cross(edge_0_1).normalize_or_zero();
 
-        if normal.y > -0.5 {
-            triangles_without_floor.push(triangle[0]);
-            triangles_without_floor.push(triangle[1]);
-            triangles_without_floor.push(triangle[2]);
-        }
-    }
-    renderer.upload_mesh_to_gpu(&triangles_without_floor);
-
-    // Naive approach to finding neighbors (closest cells)
-    let mut max = 0.0f32;
-    for node1_index in 0..nav_graph_nodes.len() {
-        // Add to circular buffer any time the value is lower than the last
-        let mut closest_distances = [f32::INFINITY, f32::INFINITY, f32::INFINITY, f32::INFINITY];
-        let mut closest_indices = [0xFFFF, 0xFFFF, 0xFFFF, 0xFFFF]; // Initialize to invalid value 0xFFFF, so we know when to end early if there's less neighbors
-        let mut circular_buffer_index = 0;
+            // Shapes only keep their triangles to block the line of sight in the nav graph
+            let output = match fit_shape {
+                Some(_) => {
+                    shape_points.extend(curr_primitive.iter().map(|vert| collision_position([vert.pos_x, vert.pos_y, vert.pos_z])));
+                    &mut shape_triangles
+                }
+                None => &mut triangles,
+            };
 
-        for node2_index in 0..nav_graph_nodes.len() {
-            if node1_index == node2_index {
+            // Polygons with more vertices, like the quads in visual meshes, can be split into a fan of triangles.
+            // Shapes always are, since their triangles only block the line of sight
+            if *arity < 3 {
+                if fit_shape.is_none() {
+                    warn!("found a face with {arity} vertices in {}, only polygons are used for collision", model.name);
+                }
                 continue;
             }
-
-            let node1 = &nav_graph_nodes[node1_index];
-            let node2 = &nav_graph_nodes[node2_index];
-            let a = glam::vec3(node1.pos_x as f32, node1.pos_y as f32 + 8.0, node1.pos_z as f32);
-            let b = glam::vec3(node2.pos_x as f32, node2.pos_y as f32 + 8.0, node2.pos_z as f32);
-            let distance = a.distance(b);
-
-            if distance >= 128.0 {
+            if *arity > 3 && !settings.triangulate_polygons && fit_shape.is_none() {
+                warn!("found polygon with more than 3 vertices in {}! make sure the collision mesh only contains triangles.", model.name);
                 continue;
             }
-
-            if distance >= closest_distances[circular_buffer_index] {
+            if !is_convex_polygon(&curr_primitive) {
+                warn!("found a concave polygon with {arity} vertices in {}, skipping it because it can't be split into a fan of triangles", model.name);
                 continue;
             }
-
-            if renderer.is_path_occupied(a * 8.0, b * 8.0, 24.0) {
-                continue;
+            for i in 1..curr_primitive.len() - 1 {
+                for j in [0, i + 1, i] {
+                    output.push(curr_primitive[j]);
+                }
             }
-
-            closest_distances[circular_buffer_index] = distance;
-            closest_indices[circular_buffer_index] = node2_index;
-            circular_buffer_index += 1;
-            circular_buffer_index %= closest_distances.len();
         }
 
-        for (i, closest_index) in closest_indices.iter().enumerate() {
-            nav_graph_nodes[node1_index].neighbors[i] = *closest_index as u16;
-            if closest_distances[i] != f32::INFINITY {
-                max = max.max(closest_distances[i]);
+        if let Some(fit_shape) = fit_shape {
+            if shape_points.is_empty() {
+                return Err(Error::InvalidData {
+                    what: "collision shape",
+                    reason: format!("{} has no vertices to fit a shape around", model.name),
+                });
             }
+            shapes.push(CollShapePSX {
+                shape: fit_shape(&shape_points),
+                terrain_id,
+            });
         }
     }
 
-    let collision_model_psx = CollModelPSX {
+    weld(&mut triangles, (settings.weld_tolerance * 1024.0).round() as i32, &mut weld_report);
+    weld_report.log();
+    if let Some(max_error) = settings.decimate {
+        decimate(&mut triangles, max_error * 1024.0).log();
+    }
+
+    let bvh = CollBvh::construct(&triangles, shapes, settings);
+    info!(
+        "built collision BVH with {} nodes and {} shapes, expected traversal cost {:.2}",
+        bvh.nodes.len(),
+        bvh.shapes.len(),
+        expected_traversal_cost(&bvh.nodes)
+    );
+
+    let nav_graphs = match settings.nav_output {
+        NavOutput::Graph | NavOutput::Both => build_nav_graphs(&triangles, &bvh.primitives, &shape_triangles, &nav_link_helpers, settings),
+        NavOutput::Navmesh => vec![],
+    };
+    let (navmesh_vertices, navmesh_polygons) = match settings.nav_output {
+        NavOutput::Navmesh | NavOutput::Both => build_navmesh(&bvh.primitives),
+        NavOutput::Graph => (vec![], vec![]),
+    };
+    if !navmesh_polygons.is_empty() {
+        info!("built navmesh with {} polygons", navmesh_polygons.len());
+    }
+    if !gameplay_objects.is_empty() {
+        info!("found {} triggers and markers", gameplay_objects.len());
+    }
+
+    let mut collision_model = CollModelPSX {
         triangles: bvh.primitives,
+        shapes: bvh.shapes,
         nodes: bvh.nodes,
         indices: bvh.indices,
-        nav_graph_nodes,
+        nav_graphs,
+        navmesh_vertices,
+        navmesh_polygons,
+        gameplay_objects,
+        wide_indices: settings.wide_indices,
+        precomputed_planes: settings.precomputed_planes,
     };
+    if !collision_model.wide_indices && collision_model.needs_wide_indices() {
+        info!(
+            "{} primitives, {} BVH nodes and {} navigation graph nodes don't fit in 16-bit indices, using 32-bit indices",
+            collision_model.n_primitives(),
+            collision_model.nodes.len(),
+            collision_model.max_nav_graph_nodes()
+        );
+        collision_model.wide_indices = true;
+    }
+    Ok(collision_model)
+}
+
+/// How an agent gets from one nav graph node to the next
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum NavLinkType {
+    Walk = 0,
+    Drop = 1,
+    Jump = 2,
+    Ladder = 3,
+}
 
-    collision_model_psx.save(Path::new(&output_col));
+impl NavLinkType {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(NavLinkType::Walk),
+            1 => Some(NavLinkType::Drop),
+            2 => Some(NavLinkType::Jump),
+            3 => Some(NavLinkType::Ladder),
+            _ => None,
+        }
+    }
 }
 
+#[derive(Debug, PartialEq)]
 pub struct CollTrianglePSX {
     pub v0: glam::IVec3,
     pub v1: glam::IVec3,
@@ -160,61 +518,342 @@ pub struct CollTrianglePSX {
 
     // Will be exported separately
     pub terrain_id: u8,
+    pub surface_type: SurfaceType,
+}
+
+/// A convex shape the runtime can test against analytically, in the same space as the triangles
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CollShape {
+    /// Axis aligned box
+    Box { min: glam::IVec3, max: glam::IVec3 },
+    Sphere { center: glam::IVec3, radius: i32 },
+    /// All points within `radius` of the line segment from `a` to `b`
+    Capsule { a: glam::IVec3, b: glam::IVec3, radius: i32 },
+}
+
+#[derive(Debug, PartialEq)]
+pub struct CollShapePSX {
+    pub shape: CollShape,
+    pub terrain_id: u8,
+}
+
+impl CollShape {
+    pub fn bounds(&self) -> Aabb {
+        match *self {
+            CollShape::Box { min, max } => Aabb { min, max },
+            CollShape::Sphere { center, radius } => Aabb {
+                min: center - radius,
+                max: center + radius,
+            },
+            CollShape::Capsule { a, b, radius } => Aabb {
+                min: a.min(b) - radius,
+                max: a.max(b) + radius,
+            },
+        }
+    }
+
+    /// The smallest axis aligned box around the points
+    pub fn fit_box(points: &[glam::IVec3]) -> Self {
+        let Aabb { min, max } = point_bounds(points);
+        CollShape::Box { min, max }
+    }
+
+    /// A sphere around the center of the points' bounding box
+    pub fn fit_sphere(points: &[glam::IVec3]) -> Self {
+        let Aabb { min, max } = point_bounds(points);
+        let center = ((min.as_i64vec3() + max.as_i64vec3()) / 2).as_ivec3();
+        CollShape::Sphere {
+            center,
+            radius: max_distance(points, |point| point.distance(center.as_dvec3())),
+        }
+    }
+
+    /// A capsule along the longest axis of the points' bounding box, with the segment as short as the radius allows
+    pub fn fit_capsule(points: &[glam::IVec3]) -> Self {
+        let Aabb { min, max } = point_bounds(points);
+        let center = (min.as_dvec3() + max.as_dvec3()) / 2.0;
+        let size = max - min;
+        let axis = if size.x >= size.y && size.x >= size.z {
+            glam::DVec3::X
+        } else if size.y >= size.z {
+            glam::DVec3::Y
+        } else {
+            glam::DVec3::Z
+        };
+
+        // Every point needs a part of the segment within the radius, which is the range around its position along the axis
+        let along = |point: glam::DVec3| (point - center).dot(axis);
+        let radius = points
+            .iter()
+            .map(|point| (point.as_dvec3() - center).reject_from_normalized(axis).length())
+            .fold(0.0, f64::max);
+        let mut start = f64::MAX;
+        let mut end = f64::MIN;
+        for point in points {
+            let point = point.as_dvec3();
+            let reach = (radius * radius - (point - center).reject_from_normalized(axis).length_squared()).max(0.0).sqrt();
+            start = start.min(along(point) + reach);
+            end = end.max(along(point) - reach);
+        }
+
+        // If the ranges overlap, any point in the overlap works as a segment of length 0
+        if start > end {
+            let middle = (start + end) / 2.0;
+            (start, end) = (middle, middle);
+        }
+        let a = (center + axis * start).round().as_ivec3();
+        let b = (center + axis * end).round().as_ivec3();
+
+        // Rounding the ends can move the segment a bit, so the radius is measured again
+        let segment = b.as_dvec3() - a.as_dvec3();
+        CollShape::Capsule {
+            a,
+            b,
+            radius: max_distance(points, |point| {
+                let t = match segment.length_squared() {
+                    0.0 => 0.0,
+                    length_squared => ((point - a.as_dvec3()).dot(segment) / length_squared).clamp(0.0, 1.0),
+                };
+                point.distance(a.as_dvec3() + segment * t)
+            }),
+        }
+    }
+}
+
+fn point_bounds(points: &[glam::IVec3]) -> Aabb {
+    Aabb {
+        min: points.iter().fold(glam::IVec3::MAX, |min, point| min.min(*point)),
+        max: points.iter().fold(glam::IVec3::MIN, |max, point| max.max(*point)),
+    }
+}
+
+/// The largest distance from any of the points to a shape, rounded up so the shape contains all of them
+fn max_distance(points: &[glam::IVec3], distance: impl Fn(glam::DVec3) -> f64) -> i32 {
+    points.iter().map(|point| distance(point.as_dvec3())).fold(0.0, f64::max).ceil() as i32
+}
+
+/// A named object the game looks up instead of colliding with
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum GameplayObjectKind {
+    /// A volume that does something when entered, like a level exit or a kill zone
+    Trigger = 0,
+    /// A point of interest, like a spawn point or a checkpoint, at the center of its bounds
+    Marker = 1,
+}
+
+impl GameplayObjectKind {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(GameplayObjectKind::Trigger),
+            1 => Some(GameplayObjectKind::Marker),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct GameplayObjectPSX {
+    pub kind: GameplayObjectKind,
+    /// Full name of the OBJ object, including the prefix
+    pub name: String,
+    /// In the same space as the triangles
+    pub bounds: Aabb,
+}
+
+/// Data the runtime would otherwise calculate from a triangle's vertices for every test.
+/// Normals are fixed point with 4096 as 1.0 like the triangle normal, and distances are in the same units as the vertices,
+/// so a point is on the plane when `normal · point / 4096 == distance`, and inside edge `i` when `edge_normals[i] · point / 4096 >= edge_distances[i]`
+#[derive(Debug, PartialEq)]
+pub struct TrianglePlanes {
+    pub distance: i32,
+    /// Normals of the edges from vertex 0 to 1, 1 to 2 and 2 to 0, in the plane of the triangle and pointing into it
+    pub edge_normals: [glam::IVec3; 3],
+    pub edge_distances: [i32; 3],
+}
+
+impl CollTrianglePSX {
+    pub fn planes(&self) -> TrianglePlanes {
+        // Distances use the rounded normals, so they match what the runtime gets with the stored values
+        let distance = |normal: glam::IVec3, point: glam::IVec3| (normal.as_dvec3().dot(point.as_dvec3()) / 4096.0).round() as i32;
+        let vertices = [self.v0, self.v1, self.v2];
+        let normal = self.normal.as_dvec3();
+        let mut edge_normals = [glam::IVec3::ZERO; 3];
+        let mut edge_distances = [0; 3];
+        for i in 0..3 {
+            let [start, end, opposite] = [vertices[i], vertices[(i + 1) % 3], vertices[(i + 2) % 3]].map(|v| v.as_dvec3());
+            let mut edge_normal = normal.cross(end - start).normalize_or_zero();
+            if edge_normal.dot(opposite - start) < 0.0 {
+                edge_normal = -edge_normal;
+            }
+            edge_normals[i] = (edge_normal * 4096.0).round().as_ivec3();
+            edge_distances[i] = distance(edge_normals[i], vertices[i]);
+        }
+        TrianglePlanes {
+            distance: distance(self.normal, self.v0),
+            edge_normals,
+            edge_distances,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum SurfaceType {
+    Floor = 0,
+    Wall = 1,
+    Ceiling = 2,
+}
+
+impl SurfaceType {
+    /// Classifies a triangle by its fixed point normal, where 4096 is 1.0 and positive Y is up
+    pub fn classify(normal: glam::IVec3, slope_limit: f32) -> Self {
+        let min_up = slope_limit.to_radians().cos();
+        let up = normal.y as f32 / 4096.0;
+        if up >= min_up {
+            SurfaceType::Floor
+        } else if up <= -min_up {
+            SurfaceType::Ceiling
+        } else {
+            SurfaceType::Wall
+        }
+    }
+
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(SurfaceType::Floor),
+            1 => Some(SurfaceType::Wall),
+            2 => Some(SurfaceType::Ceiling),
+            _ => None,
+        }
+    }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Aabb {
     pub min: glam::IVec3,
     pub max: glam::IVec3,
 }
+#[derive(Debug, PartialEq)]
 pub struct BvhNode {
     pub bounds: Aabb, // Axis aligned bounding box around all primitives inside this node
-    pub left_first: u16, // If this is a leaf, this is the index of the first primitive, otherwise, this is the index of the first of two child nodes
-    pub primitive_count: u16, // If this value is not 0, this is a leaf node
+    pub left_first: u32, // If this is a leaf, this is the index of the first primitive, otherwise, this is the index of the first of two child nodes
+    pub primitive_count: u32, // If this value is not 0, this is a leaf node
 }
 
+#[derive(Clone, Copy)]
 enum Axis {
     X,
     Y,
     Z,
 }
 
+/// The indices point at triangles first, then at shapes, so index `primitives.len() + i` is shape `i`
 struct CollBvh {
     primitives: Vec<CollTrianglePSX>,
-    indices: Vec<u16>,
+    shapes: Vec<CollShapePSX>,
+    indices: Vec<u32>,
     nodes: Vec<BvhNode>,
 
+    // Build settings
+    builder: BvhBuilder,
+    max_leaf_size: usize,
+    max_depth: usize,
+    n_oversized_leaves: usize,
+    largest_leaf: usize,
+
     // Intermediates, won't get stored in the output file
-    centers: Vec<glam::IVec3>,
+    bounds: Vec<Aabb>,
 }
 
 const COL_SCALE: i32 = 512;
 
+// Relative cost of visiting a BVH node versus testing a triangle, and the number of candidate split planes per axis
+const SAH_TRAVERSAL_COST: f64 = 1.0;
+const SAH_INTERSECT_COST: f64 = 1.0;
+const SAH_BINS: usize = 16;
+
+/// Half the surface area of a bounding box, which is all the surface area heuristic needs since it only compares ratios
+fn half_area(bounds: &Aabb) -> f64 {
+    let size = (bounds.max - bounds.min).as_dvec3().max(glam::DVec3::ZERO);
+    size.x * size.y + size.y * size.z + size.z * size.x
+}
+
+fn merge_bounds(bounds: Option<Aabb>, other: &Aabb) -> Aabb {
+    match bounds {
+        None => Aabb {
+            min: other.min,
+            max: other.max,
+        },
+        Some(bounds) => Aabb {
+            min: bounds.min.min(other.min),
+            max: bounds.max.max(other.max),
+        },
+    }
+}
+
+/// Estimates the cost of a ray or shape query against a BVH with the surface area heuristic,
+/// relative to testing a single triangle. Lower is better
+pub fn expected_traversal_cost(nodes: &[BvhNode]) -> f64 {
+    let Some(root) = nodes.first() else {
+        return 0.0;
+    };
+    let root_area = half_area(&root.bounds);
+    if root_area <= 0.0 {
+        return root.primitive_count as f64 * SAH_INTERSECT_COST;
+    }
+
+    let mut cost = 0.0;
+    let mut stack = vec![0usize];
+    while let Some(node_index) = stack.pop() {
+        let Some(node) = nodes.get(node_index) else {
+            continue;
+        };
+        let probability = half_area(&node.bounds) / root_area;
+        // Children always come after their parent, which also protects us from cycles in broken files
+        if node.primitive_count > 0 || (node.left_first as usize) <= node_index {
+            cost += probability * SAH_INTERSECT_COST * node.primitive_count as f64;
+        } else {
+            cost += probability * SAH_TRAVERSAL_COST;
+            stack.push(node.left_first as usize);
+            stack.push(node.left_first as usize + 1);
+        }
+    }
+    cost
+}
+
 impl CollBvh {
-    pub fn construct(vertices: &Vec<CollVertexPSX>) -> CollBvh {
+    pub fn construct(vertices: &[CollVertexPSX], shapes: Vec<CollShapePSX>, settings: &CollisionSettings) -> CollBvh {
+        // A traversal pops a node and pushes both its children, so a tree of depth N needs N + 1 stack entries
+        let stack_depth_limit = settings.bvh_stack_size.saturating_sub(1);
+        if settings.max_bvh_depth > stack_depth_limit {
+            warn!(
+                "max BVH depth {} needs a traversal stack of {} entries, but the runtime only has {}, limiting the depth to {stack_depth_limit}",
+                settings.max_bvh_depth,
+                settings.max_bvh_depth + 1,
+                settings.bvh_stack_size
+            );
+        }
+
         let mut bvh = CollBvh {
             primitives: vec![],
+            shapes,
             indices: vec![],
             nodes: vec![],
-            centers: vec![],
+            builder: settings.bvh_builder,
+            max_leaf_size: settings.max_leaf_size.max(1),
+            max_depth: settings.max_bvh_depth.min(stack_depth_limit),
+            n_oversized_leaves: 0,
+            largest_leaf: 0,
+            bounds: vec![],
         };
 
-        // Get primitives and their center points
+        // Get primitives and their bounds
         for triangle in vertices.chunks_exact(3) {
-            let v0 = glam::IVec3::new(
-                triangle[0].pos_x as i32 * -COL_SCALE,
-                triangle[0].pos_y as i32 * -COL_SCALE,
-                triangle[0].pos_z as i32 * -COL_SCALE,
-            );
-            let v1 = glam::IVec3::new(
-                triangle[1].pos_x as i32 * -COL_SCALE,
-                triangle[1].pos_y as i32 * -COL_SCALE,
-                triangle[1].pos_z as i32 * -COL_SCALE,
-            );
-            let v2 = glam::IVec3::new(
-                triangle[2].pos_x as i32 * -COL_SCALE,
-                triangle[2].pos_y as i32 * -COL_SCALE,
-                triangle[2].pos_z as i32 * -COL_SCALE,
-            );
+            let terrain_id = triangle[0].terrain_id as u8;
+            let v0 = collision_position([triangle[0].pos_x, triangle[0].pos_y, triangle[0].pos_z]);
+            let v1 = collision_position([triangle[1].pos_x, triangle[1].pos_y, triangle[1].pos_z]);
+            let v2 = collision_position([triangle[2].pos_x, triangle[2].pos_y, triangle[2].pos_z]);
 
             // Calculate normal
             let edge_0_2 = (v2 - v0).as_vec3();
@@ -228,13 +867,21 @@ impl CollBvh {
                 v1,
                 v2,
                 normal: normal.as_ivec3(),
-                terrain_id: 0,
-            }); // todo: unhardcode terrain id
-            bvh.centers.push((v0 + v1 + v2) / glam::IVec3::new(3, 3, 3));
+                terrain_id,
+                surface_type: SurfaceType::classify(normal.as_ivec3(), settings.slope_limit),
+            });
+            bvh.bounds.push(Aabb {
+                min: v0.min(v1.min(v2)),
+                max: v0.max(v1.max(v2)),
+            });
         }
+        for shape in &bvh.shapes {
+            bvh.bounds.push(shape.shape.bounds());
+        }
+        let n_primitives = bvh.bounds.len() as u32;
 
         // Create index array
-        bvh.indices = (0..bvh.primitives.len() as u16).collect();
+        bvh.indices = (0..n_primitives).collect();
 
         // Create root node
         bvh.nodes.push(BvhNode {
@@ -243,7 +890,7 @@ impl CollBvh {
                 max: glam::IVec3 { x: 0, y: 0, z: 0 },
             },
             left_first: 0,
-            primitive_count: bvh.primitives.len() as u16,
+            primitive_count: n_primitives,
         });
 
         // Create empty dummy node so each pair is aligned to a multiple of 2
@@ -253,33 +900,152 @@ impl CollBvh {
                 max: glam::IVec3 { x: 0, y: 0, z: 0 },
             },
             left_first: 0,
-            primitive_count: bvh.primitives.len() as u16,
+            primitive_count: n_primitives,
         });
 
         bvh.subdivide(0, 0);
 
+        if bvh.n_oversized_leaves > 0 {
+            warn!(
+                "{} BVH leaves have more than {} primitives (up to {}) because the depth limit of {} was reached",
+                bvh.n_oversized_leaves, bvh.max_leaf_size, bvh.largest_leaf, bvh.max_depth
+            );
+        }
+
         bvh
     }
 
-    fn get_bounds(&self, first: u16, count: u16) -> Aabb {
+    fn get_bounds(&self, first: u32, count: u32) -> Aabb {
         let mut result = Aabb {
             min: glam::IVec3::MAX,
             max: glam::IVec3::MIN,
         };
 
         for i in 0..count {
-            let triangle = &self.primitives[self.indices[(first + i) as usize] as usize];
-            result.max = result
-                .max
-                .max(triangle.v0.max(triangle.v1.max(triangle.v2)));
-            result.min = result
-                .min
-                .min(triangle.v0.min(triangle.v1.min(triangle.v2)));
+            let bounds = &self.bounds[self.indices[(first + i) as usize] as usize];
+            result.max = result.max.max(bounds.max);
+            result.min = result.min.min(bounds.min);
         }
 
         result
     }
 
+    /// Center of a primitive's bounding box along an axis, where `index` points into the index array
+    fn primitive_center(&self, index: u32, axis: Axis) -> i64 {
+        let bounds = &self.bounds[self.indices[index as usize] as usize];
+        let min = bounds.min.as_i64vec3();
+        let max = bounds.max.as_i64vec3();
+        let center = (min + max) / I64Vec3::new(2, 2, 2);
+        match axis {
+            Axis::X => center.x,
+            Axis::Y => center.y,
+            Axis::Z => center.z,
+        }
+    }
+
+    /// Splits the longest axis at the average vertex position. Shapes count as three vertices at the center of their bounds.
+    /// If no axis is strictly the longest, it splits X at 0, like the original splitter did
+    fn find_mean_split(&self, node_index: usize) -> (Axis, i64) {
+        // Get the average position of all the primitives
+        let node = &self.nodes[node_index];
+        let mut avg = glam::I64Vec3::new(0, 0, 0);
+        for i in node.left_first..(node.left_first + node.primitive_count) {
+            let primitive = self.indices[i as usize] as usize;
+            avg += match self.primitives.get(primitive) {
+                Some(prim) => prim.v0.as_i64vec3() + prim.v1.as_i64vec3() + prim.v2.as_i64vec3(),
+                None => {
+                    let bounds = &self.bounds[primitive];
+                    (bounds.min.as_i64vec3() + bounds.max.as_i64vec3()) / 2 * 3
+                }
+            };
+        }
+        avg /= glam::I64Vec3::splat(node.primitive_count as i64 * 3);
+
+        // Determine split axis - choose biggest axis
+        let size = node.bounds.max - node.bounds.min;
+        if size.x > size.y && size.x > size.z {
+            (Axis::X, avg.x)
+        } else if size.y > size.x && size.y > size.z {
+            (Axis::Y, avg.y)
+        } else if size.z > size.x && size.z > size.y {
+            (Axis::Z, avg.z)
+        } else {
+            (Axis::X, 0)
+        }
+    }
+
+    /// Tries evenly spaced split planes on every axis, and picks the one with the lowest surface area heuristic cost.
+    /// Returns None if no split is cheaper than keeping this node as a leaf
+    fn find_sah_split(&self, node_index: usize) -> Option<(Axis, i64)> {
+        let node = &self.nodes[node_index];
+        let first = node.left_first;
+        let count = node.primitive_count;
+        let leaf_cost = SAH_INTERSECT_COST * count as f64 * half_area(&node.bounds);
+
+        let mut best: Option<(Axis, i64)> = None;
+        let mut best_cost = leaf_cost;
+        for axis in [Axis::X, Axis::Y, Axis::Z] {
+            // Find the range of the primitive centers along this axis
+            let mut min = i64::MAX;
+            let mut max = i64::MIN;
+            for i in first..(first + count) {
+                let center = self.primitive_center(i, axis);
+                min = min.min(center);
+                max = max.max(center);
+            }
+            if min == max {
+                continue;
+            }
+
+            // Planes between the bins. A primitive goes in the bin of the last plane it's in front of
+            let planes: Vec<i64> = (1..SAH_BINS as i64).map(|k| min + (max - min) * k / SAH_BINS as i64).collect();
+            let mut bin_counts = [0usize; SAH_BINS];
+            let mut bin_bounds: [Option<Aabb>; SAH_BINS] = Default::default();
+            for i in first..(first + count) {
+                let center = self.primitive_center(i, axis);
+                let bin = planes.iter().filter(|&&plane| center > plane).count();
+                let prim_bounds = &self.bounds[self.indices[i as usize] as usize];
+                bin_counts[bin] += 1;
+                bin_bounds[bin] = Some(merge_bounds(bin_bounds[bin].take(), prim_bounds));
+            }
+
+            // Sweep from both sides to get the cost of splitting at each plane.
+            // Everything in front of plane k (bins k+1 and up) ends up in the first child
+            let mut back_counts = [0usize; SAH_BINS];
+            let mut back_areas = [0.0f64; SAH_BINS];
+            let mut back_bounds: Option<Aabb> = None;
+            let mut back_count = 0;
+            for bin in 0..SAH_BINS - 1 {
+                back_count += bin_counts[bin];
+                if let Some(bounds) = &bin_bounds[bin] {
+                    back_bounds = Some(merge_bounds(back_bounds.take(), bounds));
+                }
+                back_counts[bin] = back_count;
+                back_areas[bin] = back_bounds.as_ref().map(half_area).unwrap_or(0.0);
+            }
+            let mut front_bounds: Option<Aabb> = None;
+            let mut front_count = 0;
+            for plane_index in (0..SAH_BINS - 1).rev() {
+                front_count += bin_counts[plane_index + 1];
+                if let Some(bounds) = &bin_bounds[plane_index + 1] {
+                    front_bounds = Some(merge_bounds(front_bounds.take(), bounds));
+                }
+                if front_count == 0 || back_counts[plane_index] == 0 {
+                    continue;
+                }
+                let front_area = front_bounds.as_ref().map(half_area).unwrap_or(0.0);
+                let cost = SAH_TRAVERSAL_COST * half_area(&node.bounds)
+                    + SAH_INTERSECT_COST
+                        * (front_area * front_count as f64 + back_areas[plane_index] * back_counts[plane_index] as f64);
+                if cost < best_cost {
+                    best_cost = cost;
+                    best = Some((axis, planes[plane_index]));
+                }
+            }
+        }
+        best
+    }
+
     fn subdivide(&mut self, node_index: usize, recursion_depth: usize) {
         let leaf_display = || {
             let debug_display_recursion_depth = false;
@@ -298,76 +1064,37 @@ impl CollBvh {
             self.nodes[node_index].primitive_count,
         );
 
-        if self.nodes[node_index].primitive_count < 3 {
+        let primitive_count = self.nodes[node_index].primitive_count as usize;
+        if primitive_count <= self.max_leaf_size {
             leaf_display();
             return;
         }
 
-        // todo: implement surface area heuristic for free performance
-
-        // Get the average position of all the primitives
-        let node = &self.nodes[node_index];
-        let mut avg = glam::I64Vec3::new(0, 0, 0);
-        for i in node.left_first..(node.left_first + node.primitive_count) {
-            avg += self.primitives[self.indices[i as usize] as usize]
-                .v0
-                .as_i64vec3();
-            avg += self.primitives[self.indices[i as usize] as usize]
-                .v1
-                .as_i64vec3();
-            avg += self.primitives[self.indices[i as usize] as usize]
-                .v2
-                .as_i64vec3();
-        }
-        avg /= glam::I64Vec3::new(
-            node.primitive_count as i64 * 3,
-            node.primitive_count as i64 * 3,
-            node.primitive_count as i64 * 3,
-        );
-
-        // Determine split axis - choose biggest axis
-        let mut split_axis = Axis::X;
-        let mut split_pos = 0;
-
-        let size = node.bounds.max - node.bounds.min;
-
-        if size.x > size.y && size.x > size.z {
-            split_axis = Axis::X;
-            split_pos = avg.x;
-        }
-
-        if size.y > size.x && size.y > size.z {
-            split_axis = Axis::Y;
-            split_pos = avg.y;
+        if recursion_depth >= self.max_depth {
+            self.n_oversized_leaves += 1;
+            self.largest_leaf = self.largest_leaf.max(primitive_count);
+            leaf_display();
+            return;
         }
 
-        if size.z > size.x && size.z > size.y {
-            split_axis = Axis::Z;
-            split_pos = avg.z;
-        }
+        // The leaf is still too big, so if SAH thinks it's cheaper to keep it as it is, we split it at the mean instead
+        let (split_axis, split_pos) = match self.builder {
+            BvhBuilder::Mean => self.find_mean_split(node_index),
+            BvhBuilder::Sah => self
+                .find_sah_split(node_index)
+                .unwrap_or_else(|| self.find_mean_split(node_index)),
+        };
+        let node = &self.nodes[node_index];
 
         // Partition the index array, and get the split position
-        let split_index = {
+        let mut split_index = {
             let mut i = node.left_first;
             for j in (node.left_first)..(node.left_first + node.primitive_count) {
-                // Get min and max of the axis we want
-                let prim = &self.primitives[self.indices[j as usize] as usize];
-                let bounds = Aabb {
-                    min: prim.v0.min(prim.v1.min(prim.v2)),
-                    max: prim.v0.max(prim.v1.max(prim.v2)),
-                };
-
-                // Get center
-                let center =
-                    (bounds.min.as_i64vec3() + bounds.max.as_i64vec3()) / I64Vec3::new(2, 2, 2);
-                let center_point = match split_axis {
-                    Axis::X => center.as_ivec3().x,
-                    Axis::Y => center.as_ivec3().y,
-                    Axis::Z => center.as_ivec3().z,
-                };
-
-                // Swap primitives that are on the wrong sides of the pivot
-                if (center_point > split_pos as i32) && (j != i) {
+                // Move primitives on the positive side of the pivot to the front. The mean builder keeps a quirk of the
+                // original partition, where a primitive that is already at the split index stays on the back side, so its
+                // trees match older versions
+                let in_place = self.builder == BvhBuilder::Mean && j == i;
+                if self.primitive_center(j, split_axis) > split_pos && !in_place {
                     self.indices.swap(i as usize, j as usize);
                     i += 1;
                 }
@@ -375,16 +1102,9 @@ impl CollBvh {
             i
         };
 
-        // If splitIndex is at the end of the array, we've reached a dead end, so stop here
-        if split_index == (node.left_first + node.primitive_count) {
-            leaf_display();
-            return;
-        }
-
-        // If splitIndex and the start are the same, we've reached a dead end, so stop here
-        if split_index == node.left_first {
-            leaf_display();
-            return;
+        // If all primitives ended up on one side, split the range in half instead so the leaves stay within the size limit
+        if split_index == (node.left_first + node.primitive_count) || split_index == node.left_first {
+            split_index = node.left_first + node.primitive_count / 2;
         }
 
         // Save the start index of this node
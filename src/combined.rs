@@ -0,0 +1,85 @@
+use std::path::Path;
+
+use log::{info, warn};
+use tobj::LoadOptions;
+
+use crate::{
+    collision::{convert_collision, is_helper_object, CollisionSettings},
+    debug_obj::write_debug_obj,
+    error::Result,
+    visual::{convert_visual, VisualSettings},
+};
+
+/// Objects with a name starting with this only go into the collision model, like `COL_STAIRS_RAMP`
+pub const COLLISION_ONLY_PREFIX: &str = "COL_";
+
+/// Objects with a name starting with this only go into the visual mesh, like `NOCOL_GRASS`
+pub const VISUAL_ONLY_PREFIX: &str = "NOCOL_";
+
+/// Splits the objects of an OBJ file into the ones for the visual mesh and the ones for the collision model, in that order.
+/// The prefix is removed from `COL_` and `NOCOL_` objects, so `COL_BOX_CRATE` still becomes a box shape.
+/// Nav link helpers, triggers and markers only go into the collision model
+pub fn split_models(models: &[tobj::Model]) -> (Vec<tobj::Model>, Vec<tobj::Model>) {
+    let mut visual = vec![];
+    let mut collision = vec![];
+    for model in models {
+        if let Some(name) = model.name.strip_prefix(COLLISION_ONLY_PREFIX) {
+            collision.push(tobj::Model::new(model.mesh.clone(), name.to_string()));
+        } else if let Some(name) = model.name.strip_prefix(VISUAL_ONLY_PREFIX) {
+            visual.push(tobj::Model::new(model.mesh.clone(), name.to_string()));
+        } else {
+            if !is_helper_object(&model.name) {
+                visual.push(model.clone());
+            }
+            collision.push(model.clone());
+        }
+    }
+    (visual, collision)
+}
+
+/// Converts a single OBJ file into a mesh, texture collection and collision model, using `split_models` to decide which objects go where.
+/// Convex polygons are split into triangles for the collision model, like `CollisionSettings::triangulate_polygons` does
+pub fn obj2msh_txc_col(
+    input_obj: String,
+    output_msh: String,
+    output_txc: String,
+    output_col: String,
+    visual_settings: &VisualSettings,
+    collision_settings: &CollisionSettings,
+) -> Result<()> {
+    let (models, materials) = tobj::load_obj(
+        &input_obj,
+        &LoadOptions {
+            single_index: true,
+            ..Default::default()
+        },
+    )?;
+    let materials = materials.unwrap_or_else(|err| {
+        warn!("failed to load materials ({err}), the mesh will be untextured and all collision triangles will use terrain ID 0");
+        Vec::new()
+    });
+
+    let (visual_models, collision_models) = split_models(&models);
+    info!(
+        "{} objects for the visual mesh and {} for the collision model",
+        visual_models.len(),
+        collision_models.len()
+    );
+
+    // Visual meshes are usually made of quads, so split them up for collision instead of skipping them
+    let collision_settings = &CollisionSettings {
+        triangulate_polygons: true,
+        ..collision_settings.clone()
+    };
+
+    let texture_dir = Path::new(&input_obj).parent().unwrap_or(Path::new(""));
+    let (model_psx, txc_psx) = convert_visual(&visual_models, &materials, texture_dir, visual_settings)?;
+    let collision_model_psx = convert_collision(&collision_models, &materials, collision_settings)?;
+
+    model_psx.save(Path::new(&output_msh))?;
+    txc_psx.save(Path::new(&output_txc))?;
+    if let Some(debug_obj) = &collision_settings.debug_obj {
+        write_debug_obj(&collision_model_psx, Path::new(debug_obj))?;
+    }
+    collision_model_psx.save(Path::new(&output_col))
+}
@@ -0,0 +1,213 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use crate::{
+    collision::{GameplayObjectKind, NavLinkType, SurfaceType},
+    error::Result,
+    psx_structs::CollModelPSX,
+};
+
+/// Collision triangle and BVH coordinates per OBJ unit. The Z axis is flipped
+const COLLISION_SCALE: f32 = 512.0 * 1024.0;
+
+/// Nav graph node coordinates per OBJ unit. The Z axis is flipped
+const NAV_NODE_SCALE: f32 = 128.0;
+
+/// The 12 edges of a box, as indices into its 8 corners. Corner i has its max X if bit 0 is set, max Y for bit 1 and max Z for bit 2
+const BOX_EDGES: [(usize, usize); 12] = [
+    (0, 1), (2, 3), (4, 5), (6, 7), // along X
+    (0, 2), (1, 3), (4, 6), (5, 7), // along Y
+    (0, 4), (1, 5), (2, 6), (3, 7), // along Z
+];
+
+fn collision_to_obj(position: glam::IVec3) -> glam::Vec3 {
+    glam::vec3(position.x as f32, position.y as f32, -position.z as f32) / COLLISION_SCALE
+}
+
+/// Writes a wireframe box as 8 vertices and 12 lines
+fn write_box(obj: &mut impl Write, min: glam::Vec3, max: glam::Vec3, n_vertices: &mut usize) -> Result<()> {
+    for corner in 0..8 {
+        let x = if corner & 1 != 0 { max.x } else { min.x };
+        let y = if corner & 2 != 0 { max.y } else { min.y };
+        let z = if corner & 4 != 0 { max.z } else { min.z };
+        writeln!(obj, "v {x} {y} {z}")?;
+    }
+    for (a, b) in BOX_EDGES {
+        writeln!(obj, "l {} {}", *n_vertices + a + 1, *n_vertices + b + 1)?;
+    }
+    *n_vertices += 8;
+    Ok(())
+}
+
+fn nav_node_to_obj(pos_x: i16, pos_y: i16, pos_z: i16) -> glam::Vec3 {
+    glam::vec3(pos_x as f32, pos_y as f32, -pos_z as f32) / NAV_NODE_SCALE
+}
+
+/// A color for each terrain ID that's easy to tell apart from its neighbors, by stepping around the hue circle with the golden angle
+fn terrain_color(terrain_id: u8) -> [f32; 3] {
+    let hue = (terrain_id as f32 * 137.508) % 360.0 / 60.0;
+    let x = 1.0 - (hue % 2.0 - 1.0).abs();
+    match hue as u32 {
+        0 => [1.0, x, 0.0],
+        1 => [x, 1.0, 0.0],
+        2 => [0.0, 1.0, x],
+        3 => [0.0, x, 1.0],
+        4 => [x, 0.0, 1.0],
+        _ => [1.0, 0.0, x],
+    }
+}
+
+fn surface_name(surface_type: SurfaceType) -> &'static str {
+    match surface_type {
+        SurfaceType::Floor => "floor",
+        SurfaceType::Wall => "wall",
+        SurfaceType::Ceiling => "ceiling",
+    }
+}
+
+fn link_name(link_type: NavLinkType) -> &'static str {
+    match link_type {
+        NavLinkType::Walk => "walk",
+        NavLinkType::Drop => "drop",
+        NavLinkType::Jump => "jump",
+        NavLinkType::Ladder => "ladder",
+    }
+}
+
+/// Writes a collision model as an OBJ with an MTL next to it, to overlay on the level in a 3D editor.
+/// It contains the triangles colored by terrain ID and by surface type, the BVH, collision shape and trigger bounds as wireframe boxes,
+/// the markers as points, and every nav graph as lines per link type
+pub fn write_debug_obj(col: &CollModelPSX, obj_path: &Path) -> Result<()> {
+    let mtl_path = obj_path.with_extension("mtl");
+    let mut obj = BufWriter::new(File::create(obj_path)?);
+    let mut mtl = BufWriter::new(File::create(&mtl_path)?);
+
+    // Materials
+    let mut terrain_ids: Vec<u8> = col.triangles.iter().map(|triangle| triangle.terrain_id).collect();
+    terrain_ids.sort();
+    terrain_ids.dedup();
+    for terrain_id in &terrain_ids {
+        let [r, g, b] = terrain_color(*terrain_id);
+        writeln!(mtl, "newmtl terrain_{terrain_id}\nKd {r} {g} {b}\n")?;
+    }
+    for (surface_type, [r, g, b]) in [
+        (SurfaceType::Floor, [0.2, 0.8, 0.2]),
+        (SurfaceType::Wall, [0.8, 0.8, 0.8]),
+        (SurfaceType::Ceiling, [0.8, 0.2, 0.2]),
+    ] {
+        writeln!(mtl, "newmtl {}\nKd {r} {g} {b}\n", surface_name(surface_type))?;
+    }
+    mtl.flush()?;
+    let mtl_name = mtl_path.file_name().unwrap_or_default().to_string_lossy();
+    writeln!(obj, "mtllib {mtl_name}")?;
+
+    // OBJ indices are global and start at 1
+    let mut n_vertices = 0;
+
+    // Collision triangles, once colored by terrain ID and once by surface type. Triangles are stored with the opposite winding
+    for object in ["collision_terrain", "collision_surface"] {
+        writeln!(obj, "o {object}")?;
+        for triangle in &col.triangles {
+            for v in [triangle.v0, triangle.v2, triangle.v1] {
+                let v = collision_to_obj(v);
+                writeln!(obj, "v {} {} {}", v.x, v.y, v.z)?;
+            }
+        }
+        for (i, triangle) in col.triangles.iter().enumerate() {
+            match object {
+                "collision_terrain" => writeln!(obj, "usemtl terrain_{}", triangle.terrain_id)?,
+                _ => writeln!(obj, "usemtl {}", surface_name(triangle.surface_type))?,
+            }
+            let first = n_vertices + i * 3 + 1;
+            writeln!(obj, "f {} {} {}", first, first + 1, first + 2)?;
+        }
+        n_vertices += col.triangles.len() * 3;
+    }
+
+    // BVH bounds, one object per depth. Children always come after their parent, so broken trees can't make us loop forever
+    let mut nodes_per_depth = Vec::<Vec<usize>>::new();
+    let mut stack = vec![(0usize, 0usize)];
+    while let Some((node_index, depth)) = stack.pop() {
+        let Some(node) = col.nodes.get(node_index) else {
+            continue;
+        };
+        if nodes_per_depth.len() <= depth {
+            nodes_per_depth.resize(depth + 1, vec![]);
+        }
+        nodes_per_depth[depth].push(node_index);
+        if node.primitive_count == 0 && node.left_first as usize > node_index {
+            stack.push((node.left_first as usize + 1, depth + 1));
+            stack.push((node.left_first as usize, depth + 1));
+        }
+    }
+    for (depth, nodes) in nodes_per_depth.iter().enumerate() {
+        writeln!(obj, "o bvh_depth_{depth}")?;
+        for &node_index in nodes {
+            let bounds = &col.nodes[node_index].bounds;
+            write_box(&mut obj, collision_to_obj(bounds.min), collision_to_obj(bounds.max), &mut n_vertices)?;
+        }
+    }
+
+    // Collision shapes, drawn as their bounds
+    if !col.shapes.is_empty() {
+        writeln!(obj, "o collision_shapes")?;
+        for shape in &col.shapes {
+            let bounds = shape.shape.bounds();
+            write_box(&mut obj, collision_to_obj(bounds.min), collision_to_obj(bounds.max), &mut n_vertices)?;
+        }
+    }
+
+    // Triggers as wireframe boxes and markers as points, one object each so they keep their names
+    for object in &col.gameplay_objects {
+        writeln!(obj, "o {}", object.name)?;
+        let [min, max] = [collision_to_obj(object.bounds.min), collision_to_obj(object.bounds.max)];
+        match object.kind {
+            GameplayObjectKind::Trigger => write_box(&mut obj, min, max, &mut n_vertices)?,
+            GameplayObjectKind::Marker => {
+                let center = (min + max) / 2.0;
+                writeln!(obj, "v {} {} {}\np {}", center.x, center.y, center.z, n_vertices + 1)?;
+                n_vertices += 1;
+            }
+        }
+    }
+
+    // Nav graphs, with the links split up by type so they can be hidden separately
+    for (graph_index, graph) in col.nav_graphs.iter().enumerate() {
+        writeln!(obj, "o nav_graph_{graph_index}_nodes")?;
+        for node in &graph.nodes {
+            let position = nav_node_to_obj(node.pos_x, node.pos_y, node.pos_z);
+            writeln!(obj, "v {} {} {}", position.x, position.y, position.z)?;
+        }
+        for i in 0..graph.nodes.len() {
+            writeln!(obj, "p {}", n_vertices + i + 1)?;
+        }
+        for link_type in [NavLinkType::Walk, NavLinkType::Drop, NavLinkType::Jump, NavLinkType::Ladder] {
+            let links: Vec<(usize, u32)> = graph
+                .nodes
+                .iter()
+                .enumerate()
+                .flat_map(|(i, node)| {
+                    node.neighbors
+                        .iter()
+                        .zip(&node.link_types)
+                        .filter(move |(_, t)| **t == link_type)
+                        .map(move |(neighbor, _)| (i, *neighbor))
+                })
+                .collect();
+            if links.is_empty() {
+                continue;
+            }
+            writeln!(obj, "o nav_graph_{graph_index}_{}", link_name(link_type))?;
+            for (a, b) in links {
+                writeln!(obj, "l {} {}", n_vertices + a + 1, n_vertices + b as usize + 1)?;
+            }
+        }
+        n_vertices += graph.nodes.len();
+    }
+
+    obj.flush()?;
+    Ok(())
+}
@@ -0,0 +1,254 @@
+use std::collections::{BinaryHeap, HashMap};
+
+use glam::{DVec3, DVec4};
+use log::info;
+
+use crate::psx_structs::CollVertexPSX;
+
+/// What decimation did to a collision mesh
+#[derive(Debug, Default, PartialEq)]
+pub struct DecimateReport {
+    pub n_triangles_before: usize,
+    pub n_triangles_after: usize,
+    /// Largest distance between a moved vertex and the planes of the original triangles around it, in OBJ units
+    pub max_error: f32,
+}
+
+impl DecimateReport {
+    pub fn log(&self) {
+        info!(
+            "decimated the collision mesh from {} to {} triangles, with an error of up to {:.5}",
+            self.n_triangles_before, self.n_triangles_after, self.max_error
+        );
+    }
+}
+
+fn plane(normal: DVec3, point: DVec3) -> DVec4 {
+    normal.extend(-normal.dot(point))
+}
+
+/// Adds a plane to a vertex unless it already has it, which keeps the lists short in flat regions
+fn add_plane(planes: &mut Vec<DVec4>, plane: DVec4) {
+    if !planes.iter().any(|other| other.abs_diff_eq(plane, 1e-6)) {
+        planes.push(plane);
+    }
+}
+
+/// Moving vertex `from` onto vertex `to`. The versions tell whether either vertex changed since the cost was calculated
+struct Collapse {
+    cost: f64,
+    from: usize,
+    to: usize,
+    versions: (u32, u32),
+}
+
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == std::cmp::Ordering::Equal
+    }
+}
+
+impl Eq for Collapse {}
+
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Collapse {
+    // Reversed, so the binary heap gives us the cheapest collapse first
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        other
+            .cost
+            .total_cmp(&self.cost)
+            .then(other.from.cmp(&self.from))
+            .then(other.to.cmp(&self.to))
+    }
+}
+
+struct Mesh {
+    positions: Vec<DVec3>,
+    /// The planes of the original triangles around each vertex, including the vertices that were moved onto it
+    planes: Vec<Vec<DVec4>>,
+    versions: Vec<u32>,
+    faces: Vec<Option<[usize; 3]>>,
+    vertex_faces: Vec<Vec<usize>>,
+}
+
+impl Mesh {
+    fn normal(&self, face: [usize; 3]) -> DVec3 {
+        let [a, b, c] = face.map(|v| self.positions[v]);
+        (b - a).cross(c - a)
+    }
+
+    fn faces_of(&self, vertex: usize) -> impl Iterator<Item = (usize, [usize; 3])> + '_ {
+        self.vertex_faces[vertex].iter().filter_map(|&f| self.faces[f].map(|face| (f, face)))
+    }
+
+    fn neighbors(&self, vertex: usize) -> Vec<usize> {
+        let mut neighbors: Vec<usize> = self.faces_of(vertex).flat_map(|(_, face)| face).filter(|&v| v != vertex).collect();
+        neighbors.sort();
+        neighbors.dedup();
+        neighbors
+    }
+
+    fn collapse(&self, from: usize, to: usize) -> Collapse {
+        let position = self.positions[to].extend(1.0);
+        Collapse {
+            cost: self.planes[from].iter().chain(&self.planes[to]).map(|plane| plane.dot(position).abs()).fold(0.0, f64::max),
+            from,
+            to,
+            versions: (self.versions[from], self.versions[to]),
+        }
+    }
+
+    /// Whether collapsing keeps the mesh a valid surface: the edge still exists, no holes close up or pinch, and no triangle flips over
+    fn can_collapse(&self, from: usize, to: usize) -> bool {
+        let n_shared_faces = self.faces_of(from).filter(|(_, face)| face.contains(&to)).count();
+        if n_shared_faces == 0 {
+            return false;
+        }
+
+        // Each triangle on the edge has one vertex next to both ends, any other vertex like that would get two edges to `to`
+        let to_neighbors = self.neighbors(to);
+        let n_common = self.neighbors(from).iter().filter(|v| to_neighbors.binary_search(v).is_ok()).count();
+        if n_common != n_shared_faces {
+            return false;
+        }
+
+        self.faces_of(from).filter(|(_, face)| !face.contains(&to)).all(|(_, face)| {
+            let moved = face.map(|v| if v == from { to } else { v });
+            self.normal(moved).dot(self.normal(face)) > 0.0
+        })
+    }
+}
+
+/// Simplifies a collision mesh by moving vertices onto their neighbors, cheapest first, as long as every vertex stays within `max_error`
+/// (in PS1 units) of the planes of the original triangles around it. Flat regions merge into as few triangles as their outline allows.
+/// Open edges and edges between terrain IDs only move along themselves, so the outline of the level and its terrain stays the same
+pub fn decimate(triangles: &mut Vec<CollVertexPSX>, max_error: f32) -> DecimateReport {
+    let mut report = DecimateReport {
+        n_triangles_before: triangles.len() / 3,
+        ..Default::default()
+    };
+
+    // Share vertices between triangles, vertices are already welded so only exact matches count
+    let mut vertex_ids = HashMap::<[i16; 3], usize>::new();
+    let mut mesh = Mesh {
+        positions: vec![],
+        planes: vec![],
+        versions: vec![],
+        faces: vec![],
+        vertex_faces: vec![],
+    };
+    let mut terrain_ids = vec![];
+    for triangle in triangles.chunks_exact(3) {
+        let face = [0, 1, 2].map(|i| {
+            let position = [triangle[i].pos_x, triangle[i].pos_y, triangle[i].pos_z];
+            *vertex_ids.entry(position).or_insert_with(|| {
+                mesh.positions.push(DVec3::new(position[0] as f64, position[1] as f64, position[2] as f64));
+                mesh.planes.push(vec![]);
+                mesh.versions.push(0);
+                mesh.vertex_faces.push(vec![]);
+                mesh.positions.len() - 1
+            })
+        });
+        for v in face {
+            mesh.vertex_faces[v].push(mesh.faces.len());
+        }
+        mesh.faces.push(Some(face));
+        terrain_ids.push(triangle[0].terrain_id);
+    }
+
+    // Every vertex starts out on the planes of its triangles
+    let mut edges = HashMap::<(usize, usize), Vec<usize>>::new();
+    for (f, face) in mesh.faces.iter().enumerate() {
+        let face = face.expect("no faces are removed yet");
+        let normal = mesh.normal(face).normalize_or_zero();
+        for i in 0..3 {
+            add_plane(&mut mesh.planes[face[i]], plane(normal, mesh.positions[face[i]]));
+            let (a, b) = (face[i], face[(i + 1) % 3]);
+            edges.entry((a.min(b), a.max(b))).or_default().push(f);
+        }
+    }
+
+    // Edges that have to keep their shape also get planes through the edge, perpendicular to their triangles.
+    // Sorted, so the collapses are always tried in the same order
+    let mut edges: Vec<_> = edges.into_iter().collect();
+    edges.sort();
+    for ((a, b), faces) in &edges {
+        let is_border = faces.len() != 2 || terrain_ids[faces[0]] != terrain_ids[faces[1]];
+        if !is_border {
+            continue;
+        }
+        for &f in faces {
+            let face = mesh.faces[f].expect("no faces are removed yet");
+            let normal = (mesh.positions[*b] - mesh.positions[*a]).cross(mesh.normal(face)).normalize_or_zero();
+            for v in [*a, *b] {
+                add_plane(&mut mesh.planes[v], plane(normal, mesh.positions[*a]));
+            }
+        }
+    }
+
+    // Collapse edges until the cheapest one moves a vertex too far. A little extra makes sure flat regions merge at 0
+    let max_cost = max_error as f64 + 1e-3;
+    let mut heap = BinaryHeap::new();
+    for ((a, b), _) in &edges {
+        heap.push(mesh.collapse(*a, *b));
+        heap.push(mesh.collapse(*b, *a));
+    }
+    let mut max_cost_used = 0.0f64;
+    while let Some(collapse) = heap.pop() {
+        if collapse.cost > max_cost {
+            break;
+        }
+        let (from, to) = (collapse.from, collapse.to);
+        if collapse.versions != (mesh.versions[from], mesh.versions[to]) || !mesh.can_collapse(from, to) {
+            continue;
+        }
+        max_cost_used = max_cost_used.max(collapse.cost);
+
+        // Triangles on the edge disappear, the others move over to `to`
+        let from_faces: Vec<(usize, [usize; 3])> = mesh.faces_of(from).collect();
+        for (f, face) in from_faces {
+            if face.contains(&to) {
+                mesh.faces[f] = None;
+            } else {
+                mesh.faces[f] = Some(face.map(|v| if v == from { to } else { v }));
+                mesh.vertex_faces[to].push(f);
+            }
+        }
+        for plane in std::mem::take(&mut mesh.planes[from]) {
+            add_plane(&mut mesh.planes[to], plane);
+        }
+        mesh.versions[from] += 1;
+        mesh.versions[to] += 1;
+
+        for neighbor in mesh.neighbors(to) {
+            heap.push(mesh.collapse(to, neighbor));
+            heap.push(mesh.collapse(neighbor, to));
+        }
+    }
+
+    let mut kept = vec![];
+    for (face, terrain_id) in mesh.faces.iter().zip(terrain_ids) {
+        let Some(face) = face else {
+            continue;
+        };
+        for v in face {
+            let position = mesh.positions[*v];
+            kept.push(CollVertexPSX {
+                pos_x: position.x as i16,
+                pos_y: position.y as i16,
+                pos_z: position.z as i16,
+                terrain_id,
+            });
+        }
+    }
+    *triangles = kept;
+
+    report.n_triangles_after = triangles.len() / 3;
+    report.max_error = max_cost_used as f32 / 1024.0;
+    report
+}
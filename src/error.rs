@@ -0,0 +1,99 @@
+use std::fmt::Display;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    /// Reading or writing a file failed
+    Io(std::io::Error),
+    /// The OBJ or MTL file could not be parsed
+    ObjLoad(tobj::LoadError),
+    /// An image file could not be opened or decoded
+    ImageDecode { path: String },
+    /// An image uses a pixel format we can't convert, like greyscale or floating point
+    UnsupportedPixelFormat { path: String, format: String },
+    /// An image is bigger than the PS1 can address in one texture
+    ImageTooLarge { path: String, width: usize, height: usize },
+    /// A vertex position doesn't fit in the 16-bit fixed point coordinates
+    CoordinateOverflow { object: String, position: [f32; 3] },
+    /// A material has a parameter we don't understand
+    InvalidMaterial { material: String, reason: String },
+    /// More textures were referenced than there are texture IDs available
+    TooManyTextures { count: usize, max: usize },
+    /// A count or index doesn't fit in the field the file format stores it in
+    TooManyElements { what: &'static str, count: usize, max: usize },
+    /// The input file has an extension we don't know how to convert
+    UnsupportedInput { path: String },
+    /// The file doesn't start with the magic of the format we tried to read
+    BadMagic { expected: &'static str, found: String },
+    /// A section of the file points past the end of the file
+    OutOfBounds { what: &'static str, offset: usize, len: usize, file_size: usize },
+    /// A section of the file doesn't start at the alignment the runtime expects
+    Misaligned { what: &'static str, offset: usize, alignment: usize },
+    /// The file is structurally valid, but the values in it contradict each other
+    InvalidData { what: &'static str, reason: String },
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "I/O error: {err}"),
+            Error::ObjLoad(err) => write!(f, "failed to load OBJ file: {err}"),
+            Error::ImageDecode { path } => write!(f, "failed to decode image \"{path}\""),
+            Error::UnsupportedPixelFormat { path, format } => {
+                write!(f, "image \"{path}\" has an unsupported pixel format ({format}), only RGB and RGBA are supported")
+            }
+            Error::ImageTooLarge { path, width, height } => {
+                write!(f, "image \"{path}\" is {width}x{height}, but can not be bigger than 256x256")
+            }
+            Error::CoordinateOverflow { object, position } => write!(
+                f,
+                "vertex ({}, {}, {}) in object \"{object}\" is out of range, positions must be within +-32 units",
+                position[0], position[1], position[2]
+            ),
+            Error::InvalidMaterial { material, reason } => write!(f, "invalid material \"{material}\": {reason}"),
+            Error::TooManyTextures { count, max } => {
+                write!(f, "the model uses {count} textures, but at most {max} are supported")
+            }
+            Error::TooManyElements { what, count, max } => {
+                write!(f, "too many {what}: found {count}, but the file format supports at most {max}")
+            }
+            Error::UnsupportedInput { path } => {
+                write!(f, "don't know how to convert \"{path}\", expected an .obj or .png file")
+            }
+            Error::BadMagic { expected, found } => {
+                write!(f, "expected file magic \"{expected}\", found \"{found}\"")
+            }
+            Error::OutOfBounds { what, offset, len, file_size } => write!(
+                f,
+                "{what} at offset {offset} (size {len}) is out of bounds, the file is only {file_size} bytes"
+            ),
+            Error::Misaligned { what, offset, alignment } => {
+                write!(f, "{what} at offset {offset} is not aligned to {alignment} bytes")
+            }
+            Error::InvalidData { what, reason } => write!(f, "invalid {what}: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            Error::ObjLoad(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<tobj::LoadError> for Error {
+    fn from(err: tobj::LoadError) -> Self {
+        Error::ObjLoad(err)
+    }
+}
@@ -1,5 +1,130 @@
-pub fn validate(r: std::io::Result<usize>) {
-    if r.is_err() {
-        panic!();
+use crate::error::{Error, Result};
+
+/// Converts the position of vertex `index` to PS1 coordinates, where 1.0 is 1024 and X and Y are flipped.
+/// Values are truncated, which is what the visual mesh has always used
+pub fn position_to_psx(positions: &[f32], index: usize, object: &str) -> Result<[i16; 3]> {
+    Ok(scaled_position(positions, index, object)?.map(|value| value as i16))
+}
+
+/// Same as `position_to_psx`, but rounded to the closest PS1 unit, which halves the quantization error.
+/// The collision mesh uses this, so vertices that are close in the OBJ file end up as close as possible before welding
+pub fn position_to_psx_rounded(positions: &[f32], index: usize, object: &str) -> Result<[i16; 3]> {
+    Ok(scaled_position(positions, index, object)?.map(|value| value.round() as i16))
+}
+
+fn scaled_position(positions: &[f32], index: usize, object: &str) -> Result<[f32; 3]> {
+    let position = [positions[index * 3 + 0], positions[index * 3 + 1], positions[index * 3 + 2]];
+    let scaled = [position[0] * -1024.0, position[1] * -1024.0, position[2] * 1024.0];
+    if scaled.iter().any(|value| !(-32768.0..=32767.0).contains(value)) {
+        return Err(Error::CoordinateOverflow {
+            object: object.to_string(),
+            position,
+        });
+    }
+    Ok(scaled)
+}
+
+/// Converts PS1 coordinates back to OBJ coordinates, the inverse of `position_to_psx_rounded`
+pub fn psx_to_position(position: [i16; 3]) -> [f32; 3] {
+    [
+        -(position[0] as i32) as f32 / 1024.0,
+        -(position[1] as i32) as f32 / 1024.0,
+        position[2] as f32 / 1024.0,
+    ]
+}
+
+/// Makes sure an image has a pixel format the quantizer understands
+pub fn check_pixel_format(path: &str, depth: usize) -> Result<()> {
+    match depth {
+        3 | 4 => Ok(()),
+        1 => Err(Error::UnsupportedPixelFormat { path: path.to_string(), format: "greyscale".to_string() }),
+        2 => Err(Error::UnsupportedPixelFormat { path: path.to_string(), format: "greyscale with alpha".to_string() }),
+        _ => Err(Error::UnsupportedPixelFormat { path: path.to_string(), format: format!("{depth} channels") }),
+    }
+}
+
+/// Reads little endian values from a file buffer, with bounds checking
+pub struct Reader<'a> {
+    data: &'a [u8],
+    pub position: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8], position: usize) -> Self {
+        Self { data, position }
+    }
+
+    pub fn bytes(&mut self, len: usize, what: &'static str) -> Result<&'a [u8]> {
+        let end = self.position.checked_add(len);
+        match end {
+            Some(end) if end <= self.data.len() => {
+                let bytes = &self.data[self.position..end];
+                self.position = end;
+                Ok(bytes)
+            }
+            _ => Err(Error::OutOfBounds {
+                what,
+                offset: self.position,
+                len,
+                file_size: self.data.len(),
+            }),
+        }
+    }
+
+    pub fn u8(&mut self, what: &'static str) -> Result<u8> {
+        Ok(self.bytes(1, what)?[0])
+    }
+
+    pub fn i8(&mut self, what: &'static str) -> Result<i8> {
+        Ok(self.bytes(1, what)?[0] as i8)
+    }
+
+    pub fn u16(&mut self, what: &'static str) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.bytes(2, what)?.try_into().unwrap()))
+    }
+
+    pub fn i16(&mut self, what: &'static str) -> Result<i16> {
+        Ok(i16::from_le_bytes(self.bytes(2, what)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self, what: &'static str) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.bytes(4, what)?.try_into().unwrap()))
+    }
+
+    pub fn i32(&mut self, what: &'static str) -> Result<i32> {
+        Ok(i32::from_le_bytes(self.bytes(4, what)?.try_into().unwrap()))
+    }
+
+    pub fn ivec3(&mut self, what: &'static str) -> Result<glam::IVec3> {
+        Ok(glam::IVec3::new(self.i32(what)?, self.i32(what)?, self.i32(what)?))
+    }
+
+    /// Checks the magic at the start of the file
+    pub fn magic(&mut self, expected: &'static str) -> Result<()> {
+        let found = self.bytes(4, "file magic")?;
+        if found != expected.as_bytes() {
+            return Err(Error::BadMagic {
+                expected,
+                found: String::from_utf8_lossy(found).to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Reads a section offset from the header, and checks that it lands inside the file with the right alignment
+    pub fn offset(&mut self, base: usize, alignment: usize, what: &'static str) -> Result<usize> {
+        let offset = base + self.u32(what)? as usize;
+        if offset > self.data.len() {
+            return Err(Error::OutOfBounds {
+                what,
+                offset,
+                len: 0,
+                file_size: self.data.len(),
+            });
+        }
+        if !offset.is_multiple_of(alignment) {
+            return Err(Error::Misaligned { what, offset, alignment });
+        }
+        Ok(offset)
     }
 }
@@ -0,0 +1,462 @@
+use std::path::Path;
+
+use crate::{
+    collision::{expected_traversal_cost, CollShape, GameplayObjectKind, NavLinkType, SurfaceType},
+    error::{Error, Result},
+    helpers::Reader,
+    psx_structs::{CollModelPSX, ModelPSX, TextureCollectionPSX, NAV_NO_NEIGHBOR},
+};
+
+/// A tree of values, which can be printed as readable text or as JSON
+pub enum Value {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    List(Vec<Value>),
+    Object(Vec<(&'static str, Value)>),
+}
+
+macro_rules! impl_value_from_int {
+    ($($t:ty),*) => {
+        $(impl From<$t> for Value {
+            fn from(value: $t) -> Self {
+                Value::Int(value as i64)
+            }
+        })*
+    };
+}
+impl_value_from_int!(u8, i8, u16, i16, u32, i32, usize);
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Bool(value)
+    }
+}
+
+impl From<f32> for Value {
+    fn from(value: f32) -> Self {
+        Value::Float(value as f64)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::Str(value.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::Str(value)
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(value: Vec<T>) -> Self {
+        Value::List(value.into_iter().map(Into::into).collect())
+    }
+}
+
+impl Value {
+    pub fn to_json(&self) -> String {
+        let mut out = String::new();
+        self.write_json(&mut out);
+        out
+    }
+
+    fn write_json(&self, out: &mut String) {
+        match self {
+            Value::Bool(value) => out.push_str(&value.to_string()),
+            Value::Int(value) => out.push_str(&value.to_string()),
+            // JSON has no NaN or infinity
+            Value::Float(value) if !value.is_finite() => out.push_str("null"),
+            Value::Float(value) => out.push_str(&value.to_string()),
+            Value::Str(value) => write_json_string(value, out),
+            Value::List(values) => {
+                out.push('[');
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    value.write_json(out);
+                }
+                out.push(']');
+            }
+            Value::Object(fields) => {
+                out.push('{');
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    write_json_string(key, out);
+                    out.push(':');
+                    value.write_json(out);
+                }
+                out.push('}');
+            }
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+        match self {
+            Value::Object(_) | Value::List(_) => self.write_text(0, &mut out),
+            _ => out.push_str(&self.inline_text()),
+        }
+        out
+    }
+
+    /// Objects and lists of objects get their own indented lines, everything else is printed inline
+    fn is_inline(&self) -> bool {
+        match self {
+            Value::Object(_) => false,
+            Value::List(values) => values.iter().all(|value| matches!(value, Value::Bool(_) | Value::Int(_) | Value::Float(_) | Value::Str(_))),
+            _ => true,
+        }
+    }
+
+    fn inline_text(&self) -> String {
+        match self {
+            Value::Bool(value) => value.to_string(),
+            Value::Int(value) => value.to_string(),
+            Value::Float(value) => format!("{value:.3}"),
+            Value::Str(value) => format!("{value:?}"),
+            Value::List(values) => {
+                let values: Vec<String> = values.iter().map(Value::inline_text).collect();
+                format!("[{}]", values.join(", "))
+            }
+            Value::Object(_) => String::new(),
+        }
+    }
+
+    fn write_text(&self, indent: usize, out: &mut String) {
+        let padding = "  ".repeat(indent);
+        match self {
+            Value::Object(fields) => {
+                for (key, value) in fields {
+                    if value.is_inline() {
+                        out.push_str(&format!("{padding}{key}: {}\n", value.inline_text()));
+                    } else {
+                        out.push_str(&format!("{padding}{key}:\n"));
+                        value.write_text(indent + 1, out);
+                    }
+                }
+            }
+            Value::List(values) => {
+                for (i, value) in values.iter().enumerate() {
+                    if value.is_inline() {
+                        out.push_str(&format!("{padding}[{i}] {}\n", value.inline_text()));
+                    } else {
+                        out.push_str(&format!("{padding}[{i}]\n"));
+                        value.write_text(indent + 1, out);
+                    }
+                }
+            }
+            _ => out.push_str(&format!("{padding}{}\n", self.inline_text())),
+        }
+    }
+}
+
+fn write_json_string(value: &str, out: &mut String) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// Reads the header fields as raw 32-bit values, so we can print them as they are stored in the file
+fn header_fields(data: &[u8], names: &[&'static str]) -> Result<Value> {
+    let mut reader = Reader::new(data, 4);
+    let mut fields = vec![];
+    for name in names {
+        fields.push((*name, Value::from(reader.u32("header")?)));
+    }
+    Ok(Value::Object(fields))
+}
+
+/// Prints the contents of a .msh, .txc or .col file, detected by its file magic
+pub fn inspect_file(path: &Path, json: bool) -> Result<String> {
+    let data = std::fs::read(path)?;
+    let value = inspect(&data)?;
+    Ok(match json {
+        true => value.to_json() + "\n",
+        false => value.to_text(),
+    })
+}
+
+pub fn inspect(data: &[u8]) -> Result<Value> {
+    match data.get(0..4) {
+        Some(b"FMSH") => inspect_msh(data),
+        Some(b"FTXC") => inspect_txc(data),
+        Some(b"FCOL") => inspect_col(data),
+        _ => Err(Error::BadMagic {
+            expected: "FMSH, FTXC or FCOL",
+            found: String::from_utf8_lossy(data.get(0..4).unwrap_or(data)).to_string(),
+        }),
+    }
+}
+
+fn inspect_msh(data: &[u8]) -> Result<Value> {
+    let model = ModelPSX::read(data)?;
+    let mesh_descs = ModelPSX::read_mesh_descs(data)?;
+
+    let mut submeshes = vec![];
+    for (mesh, desc) in model.meshes.iter().zip(&mesh_descs) {
+        submeshes.push(Value::Object(vec![
+            ("name", mesh.name.as_str().into()),
+            ("vertex_start", desc.vertex_start.into()),
+            ("n_triangles", desc.n_triangles.into()),
+            ("n_quads", desc.n_quads.into()),
+            ("aabb_min", vec![desc.x_min, desc.y_min, desc.z_min].into()),
+            ("aabb_max", vec![desc.x_max, desc.y_max, desc.z_max].into()),
+        ]));
+    }
+
+    Ok(Value::Object(vec![
+        ("format", "FMSH".into()),
+        (
+            "header",
+            header_fields(
+                data,
+                &[
+                    "n_submeshes",
+                    "offset_mesh_desc",
+                    "offset_vertex_data",
+                    "offset_mesh_names",
+                    "offset_vertex_normals",
+                    "offset_lightmap_uv",
+                    "offset_lightmap_tex",
+                ],
+            )?,
+        ),
+        ("n_vertices", model.meshes.iter().map(|mesh| mesh.verts.len()).sum::<usize>().into()),
+        ("submeshes", Value::List(submeshes)),
+    ]))
+}
+
+fn inspect_txc(data: &[u8]) -> Result<Value> {
+    let txc = TextureCollectionPSX::read(data)?;
+    let cell_descs = TextureCollectionPSX::read_cell_descs(data)?;
+
+    let mut cells = vec![];
+    for (i, desc) in cell_descs.iter().enumerate() {
+        let width = if desc.texture_width == 0 { 256 } else { desc.texture_width as u32 };
+        let height = if desc.texture_height == 0 { 256 } else { desc.texture_height as u32 };
+        cells.push(Value::Object(vec![
+            ("id", i.into()),
+            ("name", txc.texture_name(i).unwrap_or_default().into()),
+            ("sector_offset", desc.sector_offset.into()),
+            ("palette_index", desc.palette_index.into()),
+            ("size", vec![width, height].into()),
+            ("bpp", desc.texture_bpp.into()),
+            ("n_fade_palettes", desc.n_palettes.into()),
+            ("avg_color", format!("{:08X}", desc.avg_color).into()),
+            ("data_size", txc.texture_cells[i].texture_data.len().into()),
+        ]));
+    }
+
+    Ok(Value::Object(vec![
+        ("format", "FTXC".into()),
+        (
+            "header",
+            header_fields(
+                data,
+                &[
+                    "n_texture_cells",
+                    "offset_cell_descs",
+                    "offset_palette_offsets",
+                    "offset_palettes",
+                    "offset_textures",
+                    "offset_names",
+                ],
+            )?,
+        ),
+        ("texture_cells", Value::List(cells)),
+    ]))
+}
+
+fn inspect_col(data: &[u8]) -> Result<Value> {
+    let col = CollModelPSX::read(data)?;
+
+    // Walk the BVH to find the depth and leaf sizes
+    let mut n_leaves = 0;
+    let mut max_depth = 0;
+    let mut max_leaf_size = 0;
+    let mut total_leaf_size = 0;
+    let mut leaf_depth_sum = 0;
+    let mut stack = vec![(0usize, 0usize)];
+    while let Some((node_index, depth)) = stack.pop() {
+        let Some(node) = col.nodes.get(node_index) else {
+            continue;
+        };
+        max_depth = max_depth.max(depth);
+        // Children always come after their parent, which also protects us from cycles in broken files
+        if node.primitive_count > 0 || (node.left_first as usize) <= node_index {
+            n_leaves += 1;
+            leaf_depth_sum += depth;
+            max_leaf_size = max_leaf_size.max(node.primitive_count as usize);
+            total_leaf_size += node.primitive_count as usize;
+        } else {
+            stack.push((node.left_first as usize, depth + 1));
+            stack.push((node.left_first as usize + 1, depth + 1));
+        }
+    }
+
+    // Count how many triangles use each terrain ID
+    let mut terrain_counts = std::collections::BTreeMap::<u8, usize>::new();
+    for triangle in &col.triangles {
+        *terrain_counts.entry(triangle.terrain_id).or_default() += 1;
+    }
+    let terrain_ids = terrain_counts
+        .into_iter()
+        .map(|(id, count)| Value::Object(vec![("id", id.into()), ("n_triangles", count.into())]))
+        .collect();
+
+    // Count how many triangles there are of each surface type
+    let count_surface = |surface_type| col.triangles.iter().filter(|triangle| triangle.surface_type == surface_type).count();
+    let surface_types = Value::Object(vec![
+        ("floor", count_surface(SurfaceType::Floor).into()),
+        ("wall", count_surface(SurfaceType::Wall).into()),
+        ("ceiling", count_surface(SurfaceType::Ceiling).into()),
+    ]);
+
+    // Count how many shapes there are of each type
+    let count_shapes = |is_type: fn(&CollShape) -> bool| col.shapes.iter().filter(|shape| is_type(&shape.shape)).count();
+    let shapes = Value::Object(vec![
+        ("box", count_shapes(|shape| matches!(shape, CollShape::Box { .. })).into()),
+        ("sphere", count_shapes(|shape| matches!(shape, CollShape::Sphere { .. })).into()),
+        ("capsule", count_shapes(|shape| matches!(shape, CollShape::Capsule { .. })).into()),
+    ]);
+
+    // Count the links in each navigation graph
+    let mut nav_graphs = vec![];
+    for graph in &col.nav_graphs {
+        let n_links: usize = graph.nodes.iter().map(|node| node.neighbors.len()).sum();
+        let n_isolated = graph.nodes.iter().filter(|node| node.neighbors.is_empty()).count();
+        let count_links = |link_type| graph.nodes.iter().flat_map(|node| &node.link_types).filter(|&&t| t == link_type).count();
+        nav_graphs.push(Value::Object(vec![
+            ("agent_height", graph.agent_height.into()),
+            ("agent_radius", graph.agent_radius.into()),
+            ("max_link_distance", graph.max_link_distance.into()),
+            ("max_neighbors", graph.max_neighbors.into()),
+            ("step_height", graph.step_height.into()),
+            ("max_drop", graph.max_drop.into()),
+            ("max_jump_distance", graph.max_jump_distance.into()),
+            ("n_nodes", graph.nodes.len().into()),
+            ("n_links", n_links.into()),
+            ("avg_neighbors", (n_links as f32 / graph.nodes.len().max(1) as f32).into()),
+            ("n_isolated_nodes", n_isolated.into()),
+            (
+                "link_types",
+                Value::Object(vec![
+                    ("walk", count_links(NavLinkType::Walk).into()),
+                    ("drop", count_links(NavLinkType::Drop).into()),
+                    ("jump", count_links(NavLinkType::Jump).into()),
+                    ("ladder", count_links(NavLinkType::Ladder).into()),
+                ]),
+            ),
+        ]));
+    }
+
+    // Triggers and markers, with their bounds in the same space as the triangles
+    let gameplay_objects = col
+        .gameplay_objects
+        .iter()
+        .map(|object| {
+            let kind = match object.kind {
+                GameplayObjectKind::Trigger => "trigger",
+                GameplayObjectKind::Marker => "marker",
+            };
+            Value::Object(vec![
+                ("kind", kind.into()),
+                ("name", object.name.as_str().into()),
+                ("min", object.bounds.min.to_array().to_vec().into()),
+                ("max", object.bounds.max.to_array().to_vec().into()),
+            ])
+        })
+        .collect();
+
+    // Count the portals in the navmesh, every portal is stored on both sides
+    let mut n_portal_sides = 0;
+    let mut n_isolated_polygons = 0;
+    for polygon in &col.navmesh_polygons {
+        let n_neighbors = polygon.neighbors.iter().filter(|&&n| n != NAV_NO_NEIGHBOR).count();
+        n_portal_sides += n_neighbors;
+        if n_neighbors == 0 {
+            n_isolated_polygons += 1;
+        }
+    }
+    let n_polygon_vertices: usize = col.navmesh_polygons.iter().map(|polygon| polygon.vertices.len()).sum();
+
+    Ok(Value::Object(vec![
+        ("format", "FCOL".into()),
+        (
+            "header",
+            header_fields(
+                data,
+                &[
+                    "version",
+                    "flags",
+                    "n_triangles",
+                    "n_nodes",
+                    "n_indices",
+                    "n_nav_graphs",
+                    "offset_triangles",
+                    "offset_terrain_ids",
+                    "offset_bvh_nodes",
+                    "offset_bvh_indices",
+                    "offset_nav_graphs",
+                    "offset_surface_types",
+                    "n_navmesh_vertices",
+                    "n_navmesh_polygons",
+                    "offset_navmesh_vertices",
+                    "offset_navmesh_polygons",
+                    "n_shapes",
+                    "offset_shapes",
+                    "n_gameplay_objects",
+                    "offset_gameplay_objects",
+                    "offset_triangle_planes",
+                ],
+            )?,
+        ),
+        ("wide_indices", col.wide_indices.into()),
+        ("precomputed_planes", col.precomputed_planes.into()),
+        ("n_triangles", col.triangles.len().into()),
+        ("terrain_ids", Value::List(terrain_ids)),
+        ("surface_types", surface_types),
+        ("n_shapes", col.shapes.len().into()),
+        ("shapes", shapes),
+        (
+            "bvh",
+            Value::Object(vec![
+                ("n_nodes", col.nodes.len().into()),
+                ("n_leaves", n_leaves.into()),
+                ("max_depth", max_depth.into()),
+                ("avg_leaf_depth", (leaf_depth_sum as f32 / n_leaves.max(1) as f32).into()),
+                ("max_leaf_size", max_leaf_size.into()),
+                ("avg_leaf_size", (total_leaf_size as f32 / n_leaves.max(1) as f32).into()),
+                ("expected_traversal_cost", (expected_traversal_cost(&col.nodes) as f32).into()),
+            ]),
+        ),
+        ("nav_graphs", Value::List(nav_graphs)),
+        (
+            "navmesh",
+            Value::Object(vec![
+                ("n_vertices", col.navmesh_vertices.len().into()),
+                ("n_polygons", col.navmesh_polygons.len().into()),
+                ("avg_polygon_vertices", (n_polygon_vertices as f32 / col.navmesh_polygons.len().max(1) as f32).into()),
+                ("n_portals", (n_portal_sides / 2).into()),
+                ("n_isolated_polygons", n_isolated_polygons.into()),
+            ]),
+        ),
+        ("gameplay_objects", Value::List(gameplay_objects)),
+    ]))
+}
@@ -0,0 +1,36 @@
+#![allow(clippy::identity_op, clippy::too_many_arguments)]
+
+//! Converts OBJ files into the FMSH, FTXC and FCOL formats used by the PS1 runtime.
+//!
+//! The `convert_*` functions work on already loaded OBJ data and return the in-memory structs,
+//! which can then be written to any `std::io::Write` using their `write` functions.
+
+use crate::psx_structs::VertexPSX;
+mod bsp;
+pub mod collision;
+pub mod combined;
+pub mod decimate;
+pub mod debug_obj;
+pub mod error;
+mod helpers;
+pub mod inspect;
+mod kmeans;
+mod nav_graph;
+pub mod navmesh;
+pub mod psx_structs;
+pub mod renderer;
+pub mod texture_page;
+pub mod visual;
+pub mod weld;
+
+pub use collision::{convert_collision, obj2col, CollisionSettings};
+pub use combined::{obj2msh_txc_col, split_models};
+pub use error::{Error, Result};
+pub use texture_page::txc_from_page;
+pub use tobj;
+pub use visual::{convert_visual, obj2msh_txc, VisualSettings};
+
+pub(crate) struct MeshGridEntry {
+    triangles: Vec<VertexPSX>,
+    quads: Vec<VertexPSX>,
+}
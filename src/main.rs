@@ -1,31 +1,21 @@
-#![allow(clippy::identity_op, clippy::too_many_arguments)]
-
-use std::path::Path;
+use std::{path::Path, process::ExitCode};
 
 use stb_image::stb_image::bindgen::stbi_set_flip_vertically_on_load;
 
-use crate::psx_structs::VertexPSX;
-mod bsp;
-mod collision;
-mod helpers;
-mod kmeans;
-mod psx_structs;
-mod renderer;
-mod texture_page;
-mod visual;
-use clap::Parser;
-
-pub struct MeshGridEntry {
-    triangles: Vec<VertexPSX>,
-    quads: Vec<VertexPSX>,
-}
+use clap::{Parser, Subcommand};
+use log::error;
+use obj2psx::{collision, combined, inspect, texture_page, visual};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Obj file input path, with the extension .obj
-    #[arg(short, long)]
-    input: String,
+    #[arg(short, long, required = true)]
+    input: Option<String>,
 
     /// Mesh file output path, without the extension
     #[arg(short, long)]
@@ -35,6 +25,11 @@ struct Cli {
     #[arg(short, long)]
     collision: bool,
 
+    /// Convert the OBJ file into both a mesh and a collision model. Objects starting with COL_ are only collision,
+    /// and objects starting with NOCOL_ are only visual. Convex quads and other polygons are split into collision triangles
+    #[arg(long, conflicts_with = "collision")]
+    both: bool,
+
     /// Whether this uses 8-bit 256x256 texture pages or 4-bit 64x64 texture cells
     #[arg(short, long)]
     page: bool,
@@ -45,10 +40,86 @@ struct Cli {
 
     /// Whether we want to print debug messages or not
     #[arg(short, long)]
-    verbose: bool
+    verbose: bool,
+
+    /// Use the CPU rasterizer for nav graph line-of-sight checks, even if OpenGL is available
+    #[arg(long)]
+    software: bool,
+
+    /// Text file mapping collision material names to terrain IDs, one "name id" pair per line
+    #[arg(long)]
+    terrain_map: Option<String>,
+
+    /// Algorithm used to build the collision BVH
+    #[arg(long, value_enum, default_value_t)]
+    bvh: collision::BvhBuilder,
+
+    /// Maximum number of triangles in a collision BVH leaf
+    #[arg(long, default_value_t = 2)]
+    max_leaf_size: usize,
+
+    /// Maximum depth of the collision BVH
+    #[arg(long, default_value_t = 31)]
+    max_bvh_depth: usize,
+
+    /// Number of entries in the runtime's BVH traversal stack
+    #[arg(long, default_value_t = 32)]
+    bvh_stack_size: usize,
+
+    /// Always use 32-bit indices in the collision file, instead of only when 16-bit indices don't fit
+    #[arg(long)]
+    wide_indices: bool,
+
+    /// Store each collision triangle's plane distance and inward edge normals, so the runtime can skip calculating them (52 bytes per triangle)
+    #[arg(long)]
+    precomputed_planes: bool,
+
+    /// Merge collision vertices that are closer together than this on every axis, in OBJ units
+    #[arg(long, default_value_t = 0.0)]
+    weld: f32,
+
+    /// Simplify the collision mesh, moving vertices up to this far from the original surfaces, in OBJ units. 0 only merges flat regions.
+    /// The nav graph has a node per floor triangle, so it gets sparser too
+    #[arg(long)]
+    decimate: Option<f32>,
+
+    /// Steepest slope that still counts as walkable floor, in degrees
+    #[arg(long, default_value_t = 60.0)]
+    slope_limit: f32,
+
+    /// Which navigation data to generate for the collision mesh
+    #[arg(long, value_enum, default_value_t)]
+    nav: collision::NavOutput,
+
+    /// Build a nav graph for an agent, as comma separated key=value pairs with the keys height, radius, neighbors, distance, step, drop, jump
+    /// (all distances in OBJ units) and bidirectional. Can be used multiple times for multiple agents
+    /// [default: height=0.0625,radius=0.0234375,neighbors=4,distance=1,step=0.0625,drop=0,jump=0]
+    #[arg(long)]
+    nav_agent: Vec<collision::NavAgent>,
+
+    /// Number of threads for the software renderer's line-of-sight checks, 0 uses every core
+    #[arg(long, default_value_t = 0)]
+    threads: usize,
+
+    /// Also write the collision triangles, BVH boxes and nav graphs to this OBJ file (plus an MTL next to it), for debugging
+    #[arg(long)]
+    debug_obj: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Print the contents of a .msh, .txc or .col file
+    Inspect {
+        /// The file to inspect
+        file: String,
+
+        /// Print the contents as JSON instead of readable text
+        #[arg(long)]
+        json: bool,
+    },
 }
 
-fn main() {
+fn main() -> ExitCode {
     let args = Cli::parse();
     unsafe {
         stbi_set_flip_vertically_on_load(0);
@@ -60,14 +131,28 @@ fn main() {
         env_logger::Builder::new().filter_level(log::LevelFilter::Info).init();
     }
 
+    match run(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            error!("{err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: Cli) -> obj2psx::Result<()> {
+    if let Some(Command::Inspect { file, json }) = args.command {
+        print!("{}", inspect::inspect_file(Path::new(&file), json)?);
+        return Ok(());
+    }
 
-    let input = args.input.clone();
+    let input = args.input.clone().unwrap_or_default();
     if input.ends_with(".obj") {
         let (output_txc, output_msh, output_col) = match args.output {
             None => (
-                args.input.replace(".obj", ".txc"),
-                args.input.replace(".obj", ".msh"),
-                args.input.replace(".obj", ".col"),
+                input.replace(".obj", ".txc"),
+                input.replace(".obj", ".msh"),
+                input.replace(".obj", ".col"),
             ),
             Some(output) => (
                 output.clone() + ".txc",
@@ -76,19 +161,48 @@ fn main() {
             ),
         };
 
-        match args.collision {
-            false => visual::obj2msh_txc(input, output_msh, output_txc, args.page, args.split),
-            true => collision::obj2col(input, output_col),
-        }
-        return;
+        let visual_settings = visual::VisualSettings {
+            using_texture_page: args.page,
+            split: args.split,
+        };
+        let collision_defaults = collision::CollisionSettings::default();
+        let collision_settings = collision::CollisionSettings {
+            software_renderer: args.software,
+            terrain_ids: match &args.terrain_map {
+                Some(path) => collision::load_terrain_map(Path::new(path))?,
+                None => Default::default(),
+            },
+            bvh_builder: args.bvh,
+            max_leaf_size: args.max_leaf_size,
+            max_bvh_depth: args.max_bvh_depth,
+            bvh_stack_size: args.bvh_stack_size,
+            wide_indices: args.wide_indices,
+            precomputed_planes: args.precomputed_planes,
+            triangulate_polygons: collision_defaults.triangulate_polygons,
+            weld_tolerance: args.weld,
+            decimate: args.decimate,
+            slope_limit: args.slope_limit,
+            nav_output: args.nav,
+            nav_agents: match args.nav_agent.is_empty() {
+                true => collision_defaults.nav_agents,
+                false => args.nav_agent,
+            },
+            threads: args.threads,
+            debug_obj: args.debug_obj,
+        };
+        return match (args.collision, args.both) {
+            (_, true) => combined::obj2msh_txc_col(input, output_msh, output_txc, output_col, &visual_settings, &collision_settings),
+            (true, false) => collision::obj2col(input, output_col, &collision_settings),
+            (false, false) => visual::obj2msh_txc(input, output_msh, output_txc, &visual_settings),
+        };
     }
     if input.ends_with(".png") {
         let output_txc = match args.output {
-            None => args.input.replace(".png", ".txc"),
+            None => input.replace(".png", ".txc"),
             Some(output) => output,
         };
-        texture_page::txc_from_page(Path::new(&input))
-            .save(Path::new(&output_txc))
-            .unwrap();
+        return texture_page::txc_from_page(Path::new(&input))?.save(Path::new(&output_txc));
     }
+
+    Err(obj2psx::Error::UnsupportedInput { path: input })
 }
@@ -0,0 +1,470 @@
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use log::{info, warn};
+
+use crate::{
+    collision::{CollTrianglePSX, CollisionSettings, NavAgent, NavLinkType, SurfaceType},
+    psx_structs::{CollVertexPSX, NavGraph, NavGraphNode},
+    renderer::Renderer,
+};
+
+/// PS1 units (1.0 in the OBJ is 1024) per nav graph node unit, so 1.0 in the OBJ is 128 in the nav graph
+const PSX_PER_NAV_NODE_UNIT: f32 = 8.0;
+
+/// Distance between the points where we look for floor under a walk link, in nav graph node units
+const FLOOR_SAMPLE_SPACING: f32 = 4.0;
+
+/// Size of the cells in the floor lookup grid, in nav graph node units
+const FLOOR_CELL_SIZE: f32 = 32.0;
+
+/// A link placed by hand with a `NAVLINK_<type>` helper object, in PS1 coordinates
+pub struct NavLinkHelper {
+    pub name: String,
+    pub from: [i16; 3],
+    pub to: [i16; 3],
+    pub link_type: NavLinkType,
+}
+
+/// Converts a position from PS1 coordinates to nav graph node units, the same way collision triangles are
+fn psx_to_nav(position: [i16; 3]) -> glam::Vec3 {
+    glam::vec3(position[0] as f32, position[1] as f32, position[2] as f32) / -PSX_PER_NAV_NODE_UNIT
+}
+
+/// Creates a navigation graph for every agent, with a node in the center of every floor triangle linked to the closest nodes the agent can reach.
+/// The triangles of collision shapes block the line of sight, but don't get nodes
+pub fn build_nav_graphs(
+    triangles: &[CollVertexPSX],
+    primitives: &[CollTrianglePSX],
+    shape_triangles: &[CollVertexPSX],
+    helpers: &[NavLinkHelper],
+    settings: &CollisionSettings,
+) -> Vec<NavGraph> {
+    let mut renderer = Renderer::new(settings.software_renderer);
+
+    // The primitives are still in the same order as the triangles, the BVH only reorders the indices
+    let mut triangles_without_floor = vec![];
+    for (triangle, primitive) in triangles.chunks(3).zip(primitives) {
+        if primitive.surface_type != SurfaceType::Floor {
+            triangles_without_floor.extend_from_slice(triangle);
+        }
+    }
+    triangles_without_floor.extend_from_slice(shape_triangles);
+    renderer.upload_mesh(&triangles_without_floor);
+    let floor = FloorGrid::new(primitives);
+
+    let mut nav_graphs = vec![];
+    for agent in &settings.nav_agents {
+        let nav_graph = build_nav_graph(primitives, agent, helpers, &floor, &mut renderer, settings.threads);
+        info!(
+            "built nav graph with {} nodes and {} links for agents with height {} and radius {}",
+            nav_graph.nodes.len(),
+            nav_graph.nodes.iter().map(|node| node.neighbors.len()).sum::<usize>(),
+            agent.height,
+            agent.radius
+        );
+        nav_graphs.push(nav_graph);
+    }
+    nav_graphs
+}
+
+fn build_nav_graph(
+    primitives: &[CollTrianglePSX],
+    agent: &NavAgent,
+    helpers: &[NavLinkHelper],
+    floor: &FloorGrid,
+    renderer: &mut Renderer,
+    threads: usize,
+) -> NavGraph {
+    let to_psx = |distance: f32| (distance * 1024.0).round() as i32;
+    let mut nav_graph = NavGraph {
+        agent_height: to_psx(agent.height),
+        agent_radius: to_psx(agent.radius),
+        max_link_distance: to_psx(agent.max_link_distance),
+        max_neighbors: agent.max_neighbors as u32,
+        step_height: to_psx(agent.step_height),
+        max_drop: to_psx(agent.max_drop),
+        max_jump_distance: to_psx(agent.max_jump_distance),
+        nodes: vec![],
+    };
+    for primitive in primitives {
+        if primitive.surface_type != SurfaceType::Floor {
+            continue;
+        }
+
+        let center = (primitive.v0 + primitive.v1 + primitive.v2) / 3;
+        nav_graph.nodes.push(NavGraphNode {
+            pos_x: (center.x / 4096) as i16,
+            pos_y: (center.y / 4096) as i16,
+            pos_z: (center.z / 4096) as i16,
+            neighbors: vec![], // We fill these in below
+            link_types: vec![],
+        });
+    }
+
+    let to_nav = |distance: i32| distance as f32 / PSX_PER_NAV_NODE_UNIT;
+    let search = NeighborSearch {
+        max_neighbors: agent.max_neighbors,
+        max_distance: to_nav(nav_graph.max_link_distance),
+        eye_height: to_nav(nav_graph.agent_height),
+        ray_width: nav_graph.agent_radius as f32,
+        step_height: to_nav(nav_graph.step_height),
+        max_drop: to_nav(nav_graph.max_drop),
+        max_jump_distance: to_nav(nav_graph.max_jump_distance),
+    };
+    let positions: Vec<glam::Vec3> = nav_graph
+        .nodes
+        .iter()
+        .map(|node| glam::vec3(node.pos_x as f32, node.pos_y as f32, node.pos_z as f32))
+        .collect();
+    let grid = NodeGrid::new(&positions, search.search_radius());
+    let progress = Progress::new("finding nav graph neighbors", positions.len());
+
+    let mut links: Vec<Vec<(u32, NavLinkType)>> = match renderer {
+        // The software renderer can be shared between threads, so we can check many nodes at once
+        Renderer::Software(software_renderer) => {
+            let software_renderer = &*software_renderer;
+            let n_threads = match threads {
+                0 => std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+                n => n,
+            };
+            let next_node = AtomicUsize::new(0);
+            let mut results = std::thread::scope(|scope| {
+                let workers: Vec<_> = (0..n_threads)
+                    .map(|_| {
+                        scope.spawn(|| {
+                            let mut results = vec![];
+                            loop {
+                                let node_index = next_node.fetch_add(1, Ordering::Relaxed);
+                                if node_index >= positions.len() {
+                                    break results;
+                                }
+                                let links = search.find_neighbors(node_index, &positions, &grid, floor, |a, b, width| {
+                                    software_renderer.is_path_occupied(a, b, width)
+                                });
+                                results.push((node_index, links));
+                                progress.advance();
+                            }
+                        })
+                    })
+                    .collect();
+                workers.into_iter().flat_map(|worker| worker.join().unwrap()).collect::<Vec<_>>()
+            });
+            results.sort_by_key(|(node_index, _)| *node_index);
+            results.into_iter().map(|(_, links)| links).collect::<Vec<_>>()
+        }
+        // OpenGL contexts belong to one thread, so the GPU renderer checks one node at a time
+        #[cfg(feature = "gpu")]
+        Renderer::Gpu(gpu_renderer) => (0..positions.len())
+            .map(|node_index| {
+                let links = search.find_neighbors(node_index, &positions, &grid, floor, |a, b, width| {
+                    gpu_renderer.is_path_occupied(a, b, width)
+                });
+                progress.advance();
+                links
+            })
+            .collect(),
+    };
+
+    add_helper_links(&mut links, &positions, helpers, search.max_distance);
+
+    if agent.bidirectional {
+        // Walking and jumping work both ways, the line of sight check doesn't depend on the direction
+        let mut reverse_links = vec![];
+        for (node_index, node_links) in links.iter().enumerate() {
+            for &(neighbor, link_type) in node_links {
+                let reversible = matches!(link_type, NavLinkType::Walk | NavLinkType::Jump);
+                if reversible && !links[neighbor as usize].iter().any(|&(target, _)| target == node_index as u32) {
+                    reverse_links.push((neighbor, node_index as u32, link_type));
+                }
+            }
+        }
+        for (from, to, link_type) in reverse_links {
+            links[from as usize].push((to, link_type));
+        }
+    }
+
+    // Keep the neighbors sorted closest first
+    for (node_index, node_links) in links.iter_mut().enumerate() {
+        let a = positions[node_index];
+        node_links.sort_by(|&(index_a, _), &(index_b, _)| {
+            let distance_a = a.distance(positions[index_a as usize]);
+            let distance_b = a.distance(positions[index_b as usize]);
+            distance_a.total_cmp(&distance_b).then(index_a.cmp(&index_b))
+        });
+    }
+
+    for (node, node_links) in nav_graph.nodes.iter_mut().zip(links) {
+        (node.neighbors, node.link_types) = node_links.into_iter().unzip();
+    }
+
+    nav_graph
+}
+
+/// Adds the links placed with helper objects, between the nodes closest to both ends. Ladders always work both ways
+fn add_helper_links(links: &mut [Vec<(u32, NavLinkType)>], positions: &[glam::Vec3], helpers: &[NavLinkHelper], max_distance: f32) {
+    let closest_node = |position: glam::Vec3| {
+        positions
+            .iter()
+            .enumerate()
+            .map(|(i, node)| (node.distance(position), i as u32))
+            .filter(|(distance, _)| *distance < max_distance)
+            .min_by(|(distance_a, _), (distance_b, _)| distance_a.total_cmp(distance_b))
+            .map(|(_, i)| i)
+    };
+    let mut add_link = |from: u32, to: u32, link_type: NavLinkType| {
+        let node_links = &mut links[from as usize];
+        match node_links.iter_mut().find(|(target, _)| *target == to) {
+            Some(link) => link.1 = link_type,
+            None => node_links.push((to, link_type)),
+        }
+    };
+
+    for helper in helpers {
+        let (Some(from), Some(to)) = (closest_node(psx_to_nav(helper.from)), closest_node(psx_to_nav(helper.to))) else {
+            warn!("navigation link {} doesn't start and end near a floor, ignoring it", helper.name);
+            continue;
+        };
+        if from == to {
+            warn!("navigation link {} starts and ends at the same node, ignoring it", helper.name);
+            continue;
+        }
+        add_link(from, to, helper.link_type);
+        if helper.link_type == NavLinkType::Ladder {
+            add_link(to, from, helper.link_type);
+        }
+    }
+}
+
+/// Buckets nav graph nodes in a grid with cells as big as the search radius, so we only have to look at the cells around a node
+struct NodeGrid {
+    cell_size: f32,
+    cells: HashMap<[i32; 3], Vec<u32>>,
+}
+
+impl NodeGrid {
+    fn cell_of(&self, position: glam::Vec3) -> [i32; 3] {
+        (position / self.cell_size).floor().as_ivec3().to_array()
+    }
+
+    fn new(positions: &[glam::Vec3], cell_size: f32) -> Self {
+        let mut grid = Self {
+            cell_size: cell_size.max(1.0),
+            cells: HashMap::new(),
+        };
+        for (i, position) in positions.iter().enumerate() {
+            let cell = grid.cell_of(*position);
+            grid.cells.entry(cell).or_default().push(i as u32);
+        }
+        grid
+    }
+
+    /// All nodes that could be within the search radius of a position
+    fn candidates(&self, position: glam::Vec3) -> Vec<u32> {
+        let cell = self.cell_of(position);
+        let mut candidates = vec![];
+        for x in -1..=1 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    if let Some(nodes) = self.cells.get(&[cell[0] + x, cell[1] + y, cell[2] + z]) {
+                        candidates.extend_from_slice(nodes);
+                    }
+                }
+            }
+        }
+        candidates
+    }
+}
+
+/// Buckets the floor triangles by their bounds on the XZ plane, so we can quickly check if there's floor somewhere
+struct FloorGrid {
+    triangles: Vec<[glam::Vec3; 3]>,
+    cells: HashMap<[i32; 2], Vec<u32>>,
+}
+
+impl FloorGrid {
+    fn cell_of(position: glam::Vec3) -> [i32; 2] {
+        [(position.x / FLOOR_CELL_SIZE).floor() as i32, (position.z / FLOOR_CELL_SIZE).floor() as i32]
+    }
+
+    fn new(primitives: &[CollTrianglePSX]) -> Self {
+        let mut grid = Self {
+            triangles: vec![],
+            cells: HashMap::new(),
+        };
+        for primitive in primitives {
+            if primitive.surface_type != SurfaceType::Floor {
+                continue;
+            }
+            let triangle = [primitive.v0, primitive.v1, primitive.v2].map(|v| v.as_vec3() / 4096.0);
+            let min = Self::cell_of(triangle[0].min(triangle[1]).min(triangle[2]));
+            let max = Self::cell_of(triangle[0].max(triangle[1]).max(triangle[2]));
+            for x in min[0]..=max[0] {
+                for z in min[1]..=max[1] {
+                    grid.cells.entry([x, z]).or_default().push(grid.triangles.len() as u32);
+                }
+            }
+            grid.triangles.push(triangle);
+        }
+        grid
+    }
+
+    /// Whether there's a floor triangle right under or over `position`, at most `tolerance` away from it vertically
+    fn has_floor_near(&self, position: glam::Vec3, tolerance: f32) -> bool {
+        let Some(triangles) = self.cells.get(&Self::cell_of(position)) else {
+            return false;
+        };
+        triangles.iter().any(|&i| {
+            let [a, b, c] = self.triangles[i as usize];
+            let area = (b.x - a.x) * (c.z - a.z) - (c.x - a.x) * (b.z - a.z);
+            if area == 0.0 {
+                return false;
+            }
+
+            // Barycentric coordinates on the XZ plane, with a bit of slack so points on shared edges count
+            let u = ((b.x - position.x) * (c.z - position.z) - (c.x - position.x) * (b.z - position.z)) / area;
+            let v = ((c.x - position.x) * (a.z - position.z) - (a.x - position.x) * (c.z - position.z)) / area;
+            let w = 1.0 - u - v;
+            let slack = -1e-3;
+            if u < slack || v < slack || w < slack {
+                return false;
+            }
+            let height = a.y * u + b.y * v + c.y * w;
+            (height - position.y).abs() <= tolerance
+        })
+    }
+
+    /// Whether there's floor all the way along a straight line between two points on the floor
+    fn has_floor_between(&self, from: glam::Vec3, to: glam::Vec3, tolerance: f32) -> bool {
+        let horizontal_distance = glam::vec2(to.x - from.x, to.z - from.z).length();
+        let n_samples = (horizontal_distance / FLOOR_SAMPLE_SPACING).ceil().max(1.0) as usize;
+        (0..=n_samples).all(|i| self.has_floor_near(from.lerp(to, i as f32 / n_samples as f32), tolerance))
+    }
+
+    /// Whether there's any floor between the heights `top` and `bottom` at the XZ position of `top`
+    fn has_floor_in_column(&self, top: glam::Vec3, bottom: f32) -> bool {
+        let half_height = (top.y - bottom) / 2.0;
+        self.has_floor_near(glam::vec3(top.x, bottom + half_height, top.z), half_height)
+    }
+}
+
+/// How nav graph nodes are linked for an agent. Distances are in nav graph node units
+struct NeighborSearch {
+    max_neighbors: usize,
+    max_distance: f32,
+    eye_height: f32,
+    /// Half the width of the line of sight check, in PS1 units
+    ray_width: f32,
+    step_height: f32,
+    max_drop: f32,
+    max_jump_distance: f32,
+}
+
+impl NeighborSearch {
+    /// Nodes further away than this can't be linked in any way
+    fn search_radius(&self) -> f32 {
+        self.max_distance.max(self.max_jump_distance) + self.max_drop.max(self.step_height)
+    }
+
+    /// Finds out how an agent can get from one node to another, if it can at all
+    fn link_type(
+        &self,
+        a: glam::Vec3,
+        b: glam::Vec3,
+        floor: &FloorGrid,
+        is_path_occupied: &mut impl FnMut(glam::Vec3, glam::Vec3, f32) -> bool,
+    ) -> Option<NavLinkType> {
+        let eye = glam::vec3(0.0, self.eye_height, 0.0);
+        let mut is_clear = |from: glam::Vec3, to: glam::Vec3| !is_path_occupied(from * PSX_PER_NAV_NODE_UNIT, to * PSX_PER_NAV_NODE_UNIT, self.ray_width);
+        let horizontal_distance = glam::vec2(b.x - a.x, b.z - a.z).length();
+        let drop = a.y - b.y;
+
+        // Walking needs floor all the way, but only when the agent can drop or jump. Otherwise a gap in the floor
+        // can't be anything else, and only the line of sight counts like it always did
+        let can_leave_floor = self.max_drop > 0.0 || self.max_jump_distance > 0.0;
+        if a.distance(b) < self.max_distance && (!can_leave_floor || floor.has_floor_between(a, b, self.step_height)) {
+            return is_clear(a + eye, b + eye).then_some(NavLinkType::Walk);
+        }
+
+        // Dropping down moves over the edge at eye height, then falls straight down without landing anywhere else
+        if drop > self.step_height && drop <= self.max_drop && horizontal_distance < self.max_distance {
+            let above_b = glam::vec3(b.x, a.y + self.eye_height, b.z);
+            let moved = horizontal_distance < 1.0 || is_clear(a + eye, above_b);
+            let landed = floor.has_floor_in_column(glam::vec3(b.x, a.y, b.z), b.y + self.step_height);
+            return (moved && !landed).then_some(NavLinkType::Drop);
+        }
+
+        // Jumping crosses a gap between floors at about the same height. Floor all the way means there's no gap, and the
+        // nodes are just too far apart to walk
+        if self.max_jump_distance <= 0.0 {
+            return None;
+        }
+        if drop.abs() <= self.step_height
+            && horizontal_distance <= self.max_jump_distance
+            && !floor.has_floor_between(a, b, self.step_height)
+        {
+            return is_clear(a + eye, b + eye).then_some(NavLinkType::Jump);
+        }
+
+        None
+    }
+
+    /// Finds the closest nodes an agent can get to from a node, closest first. Nodes at the same distance are sorted by index
+    fn find_neighbors(
+        &self,
+        node1_index: usize,
+        positions: &[glam::Vec3],
+        grid: &NodeGrid,
+        floor: &FloorGrid,
+        mut is_path_occupied: impl FnMut(glam::Vec3, glam::Vec3, f32) -> bool,
+    ) -> Vec<(u32, NavLinkType)> {
+        let a = positions[node1_index];
+        let search_radius = self.search_radius();
+        let mut candidates: Vec<(f32, u32)> = grid
+            .candidates(a)
+            .into_iter()
+            .filter(|&node2_index| node2_index as usize != node1_index)
+            .map(|node2_index| (a.distance(positions[node2_index as usize]), node2_index))
+            .filter(|(distance, _)| *distance < search_radius)
+            .collect();
+        candidates.sort_by(|(distance_a, index_a), (distance_b, index_b)| distance_a.total_cmp(distance_b).then(index_a.cmp(index_b)));
+
+        // Only the closest candidates need to be checked, we can stop as soon as we have enough
+        let mut links = Vec::with_capacity(self.max_neighbors);
+        for (_, node2_index) in candidates {
+            if links.len() == self.max_neighbors {
+                break;
+            }
+            if let Some(link_type) = self.link_type(a, positions[node2_index as usize], floor, &mut is_path_occupied) {
+                links.push((node2_index, link_type));
+            }
+        }
+        links
+    }
+}
+
+/// Logs how far along a long running step is, every 10 percent
+struct Progress {
+    what: &'static str,
+    total: usize,
+    done: AtomicUsize,
+}
+
+impl Progress {
+    fn new(what: &'static str, total: usize) -> Self {
+        Self {
+            what,
+            total,
+            done: AtomicUsize::new(0),
+        }
+    }
+
+    fn advance(&self) {
+        let done = self.done.fetch_add(1, Ordering::Relaxed) + 1;
+        if done * 10 / self.total != (done - 1) * 10 / self.total {
+            info!("{}: {}%", self.what, done * 100 / self.total);
+        }
+    }
+}
@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+
+use glam::{DVec3, IVec3};
+
+use crate::{
+    collision::{CollTrianglePSX, SurfaceType},
+    psx_structs::{NavMeshPolygon, NAVMESH_MAX_POLYGON_VERTICES, NAV_NO_NEIGHBOR},
+};
+
+/// How far a vertex can be from the plane of a polygon and still be merged into it, in collision space.
+/// This is 1 PS1 unit, so quantizing the vertices of sloped floors doesn't keep them from merging
+const PLANE_TOLERANCE: f64 = 512.0;
+
+/// The unit normal and distance of the plane through a triangle
+fn triangle_plane(vertices: &[IVec3], triangle: &[u32]) -> (DVec3, f64) {
+    let [a, b, c] = [0, 1, 2].map(|i| vertices[triangle[i] as usize].as_dvec3());
+    let normal = (b - a).cross(c - a).normalize_or_zero();
+    (normal, normal.dot(a))
+}
+
+/// Twice the signed area of a polygon seen from above, so we know which way it winds
+fn signed_area_xz(vertices: &[IVec3], polygon: &[u32]) -> i64 {
+    let mut area = 0;
+    for i in 0..polygon.len() {
+        let a = vertices[polygon[i] as usize].as_i64vec3();
+        let b = vertices[polygon[(i + 1) % polygon.len()] as usize].as_i64vec3();
+        area += a.x * b.z - b.x * a.z;
+    }
+    area
+}
+
+/// Checks if a polygon is convex when seen from above, winding the same way as `winding`. Collinear vertices are allowed
+fn is_convex_xz(vertices: &[IVec3], polygon: &[u32], winding: i64) -> bool {
+    for i in 0..polygon.len() {
+        let a = vertices[polygon[i] as usize].as_i64vec3();
+        let b = vertices[polygon[(i + 1) % polygon.len()] as usize].as_i64vec3();
+        let c = vertices[polygon[(i + 2) % polygon.len()] as usize].as_i64vec3();
+        let turn = (b.x - a.x) * (c.z - b.z) - (b.z - a.z) * (c.x - b.x);
+        if turn.signum() == -winding.signum() {
+            return false;
+        }
+    }
+    true
+}
+
+/// Merges two polygons that share the edge `a -> b` in `first` and `b -> a` in `second`
+fn merge_polygons(first: &[u32], second: &[u32], a: u32, b: u32) -> Vec<u32> {
+    // Rotate the first polygon so it runs from b to a, and the second one so it runs from a to b
+    let start = first.iter().position(|&v| v == b).unwrap();
+    let mut merged: Vec<u32> = first[start..].iter().chain(&first[..start]).copied().collect();
+    let start = second.iter().position(|&v| v == a).unwrap();
+    let second: Vec<u32> = second[start..].iter().chain(&second[..start]).copied().collect();
+    merged.extend_from_slice(&second[1..second.len() - 1]);
+    merged
+}
+
+/// Builds a navigation mesh from the floor triangles, by greedily merging neighbouring triangles into convex polygons.
+/// Positions are in the same space as the collision triangles
+pub fn build_navmesh(primitives: &[CollTrianglePSX]) -> (Vec<IVec3>, Vec<NavMeshPolygon>) {
+    // Share vertices between triangles, so we can find shared edges
+    let mut vertices = Vec::<IVec3>::new();
+    let mut vertex_lookup = HashMap::<IVec3, u32>::new();
+    // Each polygon keeps the plane of the triangle it started out as, so merging can't drift away from it bit by bit
+    let mut polygons = Vec::<(Vec<u32>, u8, (DVec3, f64))>::new();
+    for primitive in primitives {
+        if primitive.surface_type != SurfaceType::Floor {
+            continue;
+        }
+        let polygon: Vec<u32> = [primitive.v0, primitive.v1, primitive.v2]
+            .iter()
+            .map(|position| {
+                *vertex_lookup.entry(*position).or_insert_with(|| {
+                    vertices.push(*position);
+                    vertices.len() as u32 - 1
+                })
+            })
+            .collect();
+        if signed_area_xz(&vertices, &polygon) == 0 {
+            continue;
+        }
+        let plane = triangle_plane(&vertices, &polygon);
+        polygons.push((polygon, primitive.terrain_id, plane));
+    }
+
+    // Merge polygons over their shared edges until nothing can be merged anymore.
+    // Every pass touches each polygon at most once, so the edge map stays valid during the pass
+    loop {
+        let mut edges = HashMap::<(u32, u32), usize>::new();
+        for (polygon_index, (polygon, ..)) in polygons.iter().enumerate() {
+            for i in 0..polygon.len() {
+                edges.insert((polygon[i], polygon[(i + 1) % polygon.len()]), polygon_index);
+            }
+        }
+
+        let mut alive = vec![true; polygons.len()];
+        let mut touched = vec![false; polygons.len()];
+        let mut merged_any = false;
+        for polygon_index in 0..polygons.len() {
+            if touched[polygon_index] {
+                continue;
+            }
+            let (polygon, terrain_id, (normal, distance)) = polygons[polygon_index].clone();
+            let winding = signed_area_xz(&vertices, &polygon);
+            for i in 0..polygon.len() {
+                let (a, b) = (polygon[i], polygon[(i + 1) % polygon.len()]);
+                let Some(&other_index) = edges.get(&(b, a)) else {
+                    continue;
+                };
+                let (other, other_terrain_id, _) = &polygons[other_index];
+                if other_index == polygon_index || touched[other_index] || *other_terrain_id != terrain_id {
+                    continue;
+                }
+                if polygon.len() + other.len() - 2 > NAVMESH_MAX_POLYGON_VERTICES {
+                    continue;
+                }
+
+                // Convexity is only checked from above, so floors at different slopes or heights have to be kept apart here
+                let is_coplanar = other.iter().all(|&v| (normal.dot(vertices[v as usize].as_dvec3()) - distance).abs() <= PLANE_TOLERANCE);
+                if !is_coplanar {
+                    continue;
+                }
+                let merged = merge_polygons(&polygon, other, a, b);
+                let mut sorted = merged.clone();
+                sorted.sort();
+                sorted.dedup();
+                if sorted.len() != merged.len() {
+                    continue; // the polygons touch somewhere else too, merging them would make a hole
+                }
+                if signed_area_xz(&vertices, other).signum() != winding.signum() || !is_convex_xz(&vertices, &merged, winding) {
+                    continue;
+                }
+                polygons[polygon_index].0 = merged;
+                alive[other_index] = false;
+                touched[polygon_index] = true;
+                touched[other_index] = true;
+                merged_any = true;
+                break;
+            }
+        }
+
+        let mut alive = alive.into_iter();
+        polygons.retain(|_| alive.next().unwrap());
+        if !merged_any {
+            break;
+        }
+    }
+
+    // Find the portals, which are edges shared with another polygon
+    let mut edges = HashMap::<(u32, u32), u32>::new();
+    for (polygon_index, (polygon, ..)) in polygons.iter().enumerate() {
+        for i in 0..polygon.len() {
+            edges.insert((polygon[i], polygon[(i + 1) % polygon.len()]), polygon_index as u32);
+        }
+    }
+    let mut navmesh_polygons = Vec::with_capacity(polygons.len());
+    for (polygon, terrain_id, _) in polygons {
+        let neighbors = (0..polygon.len())
+            .map(|i| {
+                let (a, b) = (polygon[i], polygon[(i + 1) % polygon.len()]);
+                edges.get(&(b, a)).copied().unwrap_or(NAV_NO_NEIGHBOR)
+            })
+            .collect();
+        navmesh_polygons.push(NavMeshPolygon {
+            vertices: polygon,
+            neighbors,
+            terrain_id,
+        });
+    }
+
+    // Only keep the vertices that are still used
+    let mut remap = vec![u32::MAX; vertices.len()];
+    let mut used_vertices = vec![];
+    for polygon in &mut navmesh_polygons {
+        for vertex in &mut polygon.vertices {
+            if remap[*vertex as usize] == u32::MAX {
+                remap[*vertex as usize] = used_vertices.len() as u32;
+                used_vertices.push(vertices[*vertex as usize]);
+            }
+            *vertex = remap[*vertex as usize];
+        }
+    }
+
+    (used_vertices, navmesh_polygons)
+}
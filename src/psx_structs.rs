@@ -1,10 +1,43 @@
-use std::{fs::File, io::Write, path::Path};
+use std::{fs::File, io::{BufWriter, Write}, path::Path};
 
 use crate::{
-    collision::{BvhNode, CollTrianglePSX},
-    helpers::validate,
+    collision::{Aabb, BvhNode, CollShape, CollShapePSX, CollTrianglePSX, GameplayObjectKind, GameplayObjectPSX, NavLinkType, SurfaceType},
+    error::{Error, Result},
+    helpers::Reader,
 };
-#[derive(Clone, Copy)]
+
+const MSH_HEADER_SIZE: usize = 32;
+const TXC_HEADER_SIZE: usize = 28;
+const COL_HEADER_SIZE: usize = 88;
+
+/// Version of the FCOL format written by this tool
+pub const FCOL_VERSION: u32 = 1;
+
+/// FCOL header flag: BVH nodes, BVH indices and navigation links are stored as 32-bit values instead of 16-bit
+pub const FCOL_FLAG_WIDE_INDICES: u32 = 1 << 0;
+
+/// FCOL header flag: the triangle planes section contains the plane distance and edge normals of every triangle
+pub const FCOL_FLAG_PRECOMPUTED_PLANES: u32 = 1 << 1;
+
+/// Size of a triangle's precomputed planes: the plane distance, then the normal and distance of each edge
+const TRIANGLE_PLANES_SIZE: usize = 52;
+
+/// Neighbor index used for navmesh edges without a polygon on the other side. It's stored as 0xFFFF in files with 16-bit indices
+pub const NAV_NO_NEIGHBOR: u32 = u32::MAX;
+
+/// Size of an entry in the FCOL navigation graph table
+const NAV_GRAPH_ENTRY_SIZE: usize = 48;
+
+/// Size of a collision shape in an FCOL file: the shape type, terrain ID, padding and 7 values whose meaning depends on the type
+const COL_SHAPE_SIZE: usize = 32;
+
+/// Size of a trigger or marker in an FCOL file: the kind, padding, bounds and the offset of its name
+const GAMEPLAY_OBJECT_SIZE: usize = 32;
+
+/// Navmesh polygons are stored with room for this many vertices, so every polygon has the same size in the file
+pub const NAVMESH_MAX_POLYGON_VERTICES: usize = 6;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct VertexPSX {
     pub pos_x: i16,
     pub pos_y: i16,
@@ -25,26 +58,234 @@ pub struct CollVertexPSX {
     pub pos_x: i16,
     pub pos_y: i16,
     pub pos_z: i16,
-    pub _terrain_id: u16,
+    pub terrain_id: u16,
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct NavGraphNode {
     pub pos_x: i16,
     pub pos_y: i16,
     pub pos_z: i16,
-    pub neighbors: [u16; 4],
+    pub neighbors: Vec<u32>,
+    /// How to get to each neighbor
+    pub link_types: Vec<NavLinkType>,
+}
+
+/// A navigation graph built for one agent size. Distances are in PS1 units, where 1.0 in the OBJ is 1024
+#[derive(Debug, PartialEq)]
+pub struct NavGraph {
+    pub agent_height: i32,
+    pub agent_radius: i32,
+    pub max_link_distance: i32,
+    pub max_neighbors: u32,
+    pub step_height: i32,
+    pub max_drop: i32,
+    pub max_jump_distance: i32,
+    pub nodes: Vec<NavGraphNode>,
+}
+
+/// A convex polygon in the navmesh, seen from above
+#[derive(Debug, PartialEq)]
+pub struct NavMeshPolygon {
+    pub vertices: Vec<u32>,
+    /// The polygon on the other side of each edge, where edge i goes from vertex i to vertex i + 1
+    pub neighbors: Vec<u32>,
+    pub terrain_id: u8,
 }
 
+#[derive(Debug, PartialEq)]
 pub struct CollModelPSX {
     pub triangles: Vec<CollTrianglePSX>,
+    /// BVH indices starting at the number of triangles point at these
+    pub shapes: Vec<CollShapePSX>,
     pub nodes: Vec<BvhNode>,
-    pub indices: Vec<u16>,
-    pub nav_graph_nodes: Vec<NavGraphNode>,
+    pub indices: Vec<u32>,
+    pub nav_graphs: Vec<NavGraph>,
+    /// Navmesh vertex positions, in the same space as the triangles
+    pub navmesh_vertices: Vec<glam::IVec3>,
+    pub navmesh_polygons: Vec<NavMeshPolygon>,
+    /// Triggers and markers, which aren't part of the BVH
+    pub gameplay_objects: Vec<GameplayObjectPSX>,
+    /// Store indices as 32-bit values. This is required when there are 65535 or more primitives, BVH nodes or nodes in a navigation graph
+    pub wide_indices: bool,
+    /// Store the plane distance and edge normals of every triangle, calculated from the triangles with `CollTrianglePSX::planes`
+    pub precomputed_planes: bool,
+}
+
+/// The FCOL header, with the offsets already converted to absolute positions in the file
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CollHeader {
+    pub version: u32,
+    pub flags: u32,
+    pub n_triangles: usize,
+    pub n_nodes: usize,
+    pub n_indices: usize,
+    pub n_nav_graphs: usize,
+    pub triangle_data_offset: usize,
+    pub terrain_id_offset: usize,
+    pub bvh_nodes_offset: usize,
+    pub bvh_indices_offset: usize,
+    pub nav_graphs_offset: usize,
+    pub surface_type_offset: usize,
+    pub n_navmesh_vertices: usize,
+    pub n_navmesh_polygons: usize,
+    pub navmesh_vertices_offset: usize,
+    pub navmesh_polygons_offset: usize,
+    pub n_shapes: usize,
+    pub shapes_offset: usize,
+    pub n_gameplay_objects: usize,
+    pub gameplay_objects_offset: usize,
+    pub triangle_planes_offset: usize,
+}
+
+impl CollHeader {
+    pub fn read(data: &[u8]) -> Result<Self> {
+        let mut header = Reader::new(data, 0);
+        header.magic("FCOL")?;
+        let version = header.u32("version")?;
+        if version != FCOL_VERSION {
+            return Err(Error::InvalidData {
+                what: "FCOL header",
+                reason: format!("unsupported version {version}, expected {FCOL_VERSION}"),
+            });
+        }
+        Ok(Self {
+            version,
+            flags: header.u32("flags")?,
+            n_triangles: header.u32("triangle count")? as usize,
+            n_nodes: header.u32("node count")? as usize,
+            n_indices: header.u32("index count")? as usize,
+            n_nav_graphs: header.u32("navigation graph count")? as usize,
+            triangle_data_offset: header.offset(COL_HEADER_SIZE, 4, "triangle data")?,
+            terrain_id_offset: header.offset(COL_HEADER_SIZE, 4, "terrain IDs")?,
+            bvh_nodes_offset: header.offset(COL_HEADER_SIZE, 4, "BVH nodes")?,
+            bvh_indices_offset: header.offset(COL_HEADER_SIZE, 4, "BVH indices")?,
+            nav_graphs_offset: header.offset(COL_HEADER_SIZE, 4, "navigation graphs")?,
+            surface_type_offset: header.offset(COL_HEADER_SIZE, 4, "surface types")?,
+            n_navmesh_vertices: header.u32("navmesh vertex count")? as usize,
+            n_navmesh_polygons: header.u32("navmesh polygon count")? as usize,
+            navmesh_vertices_offset: header.offset(COL_HEADER_SIZE, 4, "navmesh vertices")?,
+            navmesh_polygons_offset: header.offset(COL_HEADER_SIZE, 4, "navmesh polygons")?,
+            n_shapes: header.u32("shape count")? as usize,
+            shapes_offset: header.offset(COL_HEADER_SIZE, 4, "shapes")?,
+            n_gameplay_objects: header.u32("gameplay object count")? as usize,
+            gameplay_objects_offset: header.offset(COL_HEADER_SIZE, 4, "gameplay objects")?,
+            triangle_planes_offset: header.offset(COL_HEADER_SIZE, 4, "triangle planes")?,
+        })
+    }
+
+    pub fn wide_indices(&self) -> bool {
+        self.flags & FCOL_FLAG_WIDE_INDICES != 0
+    }
+
+    pub fn precomputed_planes(&self) -> bool {
+        self.flags & FCOL_FLAG_PRECOMPUTED_PLANES != 0
+    }
+
+    /// Every section with its absolute offset, size in bytes and alignment
+    pub fn sections(&self) -> [(&'static str, usize, usize, usize); 11] {
+        let index_size = if self.wide_indices() { 4 } else { 2 };
+        let triangle_planes_size = if self.precomputed_planes() { TRIANGLE_PLANES_SIZE } else { 0 };
+        let navmesh_polygon_size = 4 + NAVMESH_MAX_POLYGON_VERTICES * 2 * index_size;
+        [
+            ("triangle data", self.triangle_data_offset, self.n_triangles * 48, 4),
+            ("terrain IDs", self.terrain_id_offset, self.n_triangles, 4),
+            ("surface types", self.surface_type_offset, self.n_triangles, 4),
+            ("BVH nodes", self.bvh_nodes_offset, self.n_nodes * (24 + 2 * index_size), 4),
+            ("BVH indices", self.bvh_indices_offset, self.n_indices * index_size, 4),
+            ("navigation graphs", self.nav_graphs_offset, self.n_nav_graphs * NAV_GRAPH_ENTRY_SIZE, 4),
+            ("navmesh vertices", self.navmesh_vertices_offset, self.n_navmesh_vertices * 12, 4),
+            ("navmesh polygons", self.navmesh_polygons_offset, self.n_navmesh_polygons * navmesh_polygon_size, 4),
+            ("shapes", self.shapes_offset, self.n_shapes * COL_SHAPE_SIZE, 4),
+            ("gameplay objects", self.gameplay_objects_offset, self.n_gameplay_objects * GAMEPLAY_OBJECT_SIZE, 4),
+            ("triangle planes", self.triangle_planes_offset, self.n_triangles * triangle_planes_size, 4),
+        ]
+    }
+
+    /// Checks that every section fits inside the file and is aligned the way the runtime expects
+    pub fn verify(&self, file_size: usize) -> Result<()> {
+        for (what, offset, len, alignment) in self.sections() {
+            if offset + len > file_size {
+                return Err(Error::OutOfBounds { what, offset, len, file_size });
+            }
+            if !offset.is_multiple_of(alignment) {
+                return Err(Error::Misaligned { what, offset, alignment });
+            }
+        }
+        Ok(())
+    }
 }
 
 impl CollModelPSX {
-    pub fn save(&self, output_col: &Path) {
+    /// Saves the file, then reads it back to make sure the runtime will be able to load it
+    pub fn save(&self, output_col: &Path) -> Result<()> {
+        let mut file = BufWriter::new(File::create(output_col)?);
+        self.write(&mut file)?;
+        file.flush()?;
+        drop(file);
+
+        let data = std::fs::read(output_col)?;
+        CollHeader::read(&data)?.verify(data.len())?;
+        if Self::read(&data)? != *self {
+            return Err(Error::InvalidData {
+                what: "FCOL file",
+                reason: format!("{} doesn't contain the collision model that was written to it", output_col.display()),
+            });
+        }
+        Ok(())
+    }
+
+    /// Whether any of the counts are too big to reference with 16-bit indices. 0xFFFF is reserved for missing navmesh neighbors
+    pub fn needs_wide_indices(&self) -> bool {
+        let max = u16::MAX as usize;
+        self.n_primitives() >= max
+            || self.nodes.len() >= max
+            || self.max_nav_graph_nodes() >= max
+            || self.navmesh_vertices.len() >= max
+            || self.navmesh_polygons.len() >= max
+    }
+
+    /// Number of triangles and shapes the BVH indices can point at
+    pub fn n_primitives(&self) -> usize {
+        self.triangles.len() + self.shapes.len()
+    }
+
+    /// Number of nodes in the biggest navigation graph
+    pub fn max_nav_graph_nodes(&self) -> usize {
+        self.nav_graphs.iter().map(|graph| graph.nodes.len()).max().unwrap_or_default()
+    }
+
+    pub fn write<W: Write>(&self, file: &mut W) -> Result<()> {
+        if !self.wide_indices && self.needs_wide_indices() {
+            return Err(Error::TooManyElements {
+                what: "primitives, BVH nodes or navigation elements for 16-bit indices",
+                count: [
+                    self.n_primitives(),
+                    self.nodes.len(),
+                    self.max_nav_graph_nodes(),
+                    self.navmesh_vertices.len(),
+                    self.navmesh_polygons.len(),
+                ]
+                .into_iter()
+                .max()
+                .unwrap_or_default(),
+                max: u16::MAX as usize - 1,
+            });
+        }
+        let mut flags = 0;
+        if self.wide_indices {
+            flags |= FCOL_FLAG_WIDE_INDICES;
+        }
+        if self.precomputed_planes {
+            flags |= FCOL_FLAG_PRECOMPUTED_PLANES;
+        }
+
+        // Indices are either 16 or 32 bits depending on the flags. Truncating NAV_NO_NEIGHBOR gives 0xFFFF
+        let push_index = |binary_section: &mut Vec<u8>, index: u32| match self.wide_indices {
+            true => binary_section.extend_from_slice(&index.to_le_bytes()),
+            false => binary_section.extend_from_slice(&(index as u16).to_le_bytes()),
+        };
+
         // Populate binary section and fill in offsets
         let mut binary_section = Vec::<u8>::new();
 
@@ -66,7 +307,7 @@ impl CollModelPSX {
         }
 
         // Terrain ID
-        while (binary_section.len() % 4) != 0 {
+        while !binary_section.len().is_multiple_of(4) {
             binary_section.push(0);
         }
         let terrain_id_offset = binary_section.len() as u32;
@@ -74,8 +315,17 @@ impl CollModelPSX {
             binary_section.push(triangle.terrain_id);
         }
 
+        // Surface types
+        while !binary_section.len().is_multiple_of(4) {
+            binary_section.push(0);
+        }
+        let surface_type_offset = binary_section.len() as u32;
+        for triangle in &self.triangles {
+            binary_section.push(triangle.surface_type as u8);
+        }
+
         // BVH nodes
-        while (binary_section.len() % 4) != 0 {
+        while !binary_section.len().is_multiple_of(4) {
             binary_section.push(0);
         }
         let bvh_nodes_offset = binary_section.len() as u32;
@@ -86,58 +336,477 @@ impl CollModelPSX {
             binary_section.extend_from_slice(&node.bounds.max.x.to_le_bytes());
             binary_section.extend_from_slice(&node.bounds.max.y.to_le_bytes());
             binary_section.extend_from_slice(&node.bounds.max.z.to_le_bytes());
-            binary_section.extend_from_slice(&node.left_first.to_le_bytes());
-            binary_section.extend_from_slice(&node.primitive_count.to_le_bytes());
+            push_index(&mut binary_section, node.left_first);
+            push_index(&mut binary_section, node.primitive_count);
         }
 
         // BVH indices
-        while (binary_section.len() % 4) != 0 {
+        while !binary_section.len().is_multiple_of(4) {
             binary_section.push(0);
         }
         let bvh_indices_offset = binary_section.len() as u32;
         for index in &self.indices {
-            binary_section.extend_from_slice(&(*index).to_le_bytes());
+            push_index(&mut binary_section, *index);
+        }
+
+        // Navigation graphs. The table has an entry for each graph, which we fill in once we know where its nodes and links are
+        while !binary_section.len().is_multiple_of(4) {
+            binary_section.push(0);
+        }
+        let nav_graphs_offset = binary_section.len() as u32;
+        binary_section.resize(binary_section.len() + self.nav_graphs.len() * NAV_GRAPH_ENTRY_SIZE, 0);
+        for (graph_index, graph) in self.nav_graphs.iter().enumerate() {
+            // Nodes, each with the range of its neighbors in the link list
+            let nodes_offset = binary_section.len() as u32;
+            let mut n_links = 0u32;
+            for node in &graph.nodes {
+                if node.link_types.len() != node.neighbors.len() {
+                    return Err(Error::InvalidData {
+                        what: "navigation graph node",
+                        reason: format!("{} neighbors and {} link types", node.neighbors.len(), node.link_types.len()),
+                    });
+                }
+                if node.neighbors.len() > u16::MAX as usize {
+                    return Err(Error::TooManyElements {
+                        what: "navigation graph links on one node",
+                        count: node.neighbors.len(),
+                        max: u16::MAX as usize,
+                    });
+                }
+                binary_section.extend_from_slice(&node.pos_x.to_le_bytes());
+                binary_section.extend_from_slice(&node.pos_y.to_le_bytes());
+                binary_section.extend_from_slice(&node.pos_z.to_le_bytes());
+                binary_section.extend_from_slice(&(node.neighbors.len() as u16).to_le_bytes());
+                binary_section.extend_from_slice(&n_links.to_le_bytes());
+                n_links += node.neighbors.len() as u32;
+            }
+
+            // Links
+            let links_offset = binary_section.len() as u32;
+            for node in &graph.nodes {
+                for neighbor in &node.neighbors {
+                    push_index(&mut binary_section, *neighbor);
+                }
+            }
+            while !binary_section.len().is_multiple_of(4) {
+                binary_section.push(0);
+            }
+
+            // Link types, in the same order as the links
+            let link_types_offset = binary_section.len() as u32;
+            for node in &graph.nodes {
+                for link_type in &node.link_types {
+                    binary_section.push(*link_type as u8);
+                }
+            }
+            while !binary_section.len().is_multiple_of(4) {
+                binary_section.push(0);
+            }
+
+            let mut entry = Vec::with_capacity(NAV_GRAPH_ENTRY_SIZE);
+            entry.extend_from_slice(&(graph.nodes.len() as u32).to_le_bytes());
+            entry.extend_from_slice(&n_links.to_le_bytes());
+            entry.extend_from_slice(&nodes_offset.to_le_bytes());
+            entry.extend_from_slice(&links_offset.to_le_bytes());
+            entry.extend_from_slice(&link_types_offset.to_le_bytes());
+            entry.extend_from_slice(&graph.agent_height.to_le_bytes());
+            entry.extend_from_slice(&graph.agent_radius.to_le_bytes());
+            entry.extend_from_slice(&graph.max_link_distance.to_le_bytes());
+            entry.extend_from_slice(&graph.max_neighbors.to_le_bytes());
+            entry.extend_from_slice(&graph.step_height.to_le_bytes());
+            entry.extend_from_slice(&graph.max_drop.to_le_bytes());
+            entry.extend_from_slice(&graph.max_jump_distance.to_le_bytes());
+            let entry_offset = nav_graphs_offset as usize + graph_index * NAV_GRAPH_ENTRY_SIZE;
+            binary_section[entry_offset..entry_offset + NAV_GRAPH_ENTRY_SIZE].copy_from_slice(&entry);
+        }
+
+        // Navmesh vertices
+        while !binary_section.len().is_multiple_of(4) {
+            binary_section.push(0);
+        }
+        let navmesh_vertices_offset = binary_section.len() as u32;
+        for vertex in &self.navmesh_vertices {
+            binary_section.extend_from_slice(&vertex.x.to_le_bytes());
+            binary_section.extend_from_slice(&vertex.y.to_le_bytes());
+            binary_section.extend_from_slice(&vertex.z.to_le_bytes());
+        }
+
+        // Navmesh polygons, unused vertex and neighbor slots are filled with NAV_NO_NEIGHBOR
+        let navmesh_polygons_offset = binary_section.len() as u32;
+        for polygon in &self.navmesh_polygons {
+            if !(3..=NAVMESH_MAX_POLYGON_VERTICES).contains(&polygon.vertices.len()) || polygon.neighbors.len() != polygon.vertices.len() {
+                return Err(Error::InvalidData {
+                    what: "navmesh polygon",
+                    reason: format!("{} vertices and {} neighbors", polygon.vertices.len(), polygon.neighbors.len()),
+                });
+            }
+            binary_section.push(polygon.vertices.len() as u8);
+            binary_section.push(polygon.terrain_id);
+            binary_section.extend_from_slice(&0u16.to_le_bytes());
+            for list in [&polygon.vertices, &polygon.neighbors] {
+                for i in 0..NAVMESH_MAX_POLYGON_VERTICES {
+                    push_index(&mut binary_section, list.get(i).copied().unwrap_or(NAV_NO_NEIGHBOR));
+                }
+            }
         }
 
-        // Navigation graph
-        while (binary_section.len() % 4) != 0 {
+        // Shapes. The values are the minimum and maximum for boxes, the center and radius for spheres, and both ends and the radius for capsules
+        while !binary_section.len().is_multiple_of(4) {
             binary_section.push(0);
         }
-        let nav_graph_offset = binary_section.len() as u32; // as long as the node struct won't contain any 4-byte aligned things we're good
+        let shapes_offset = binary_section.len() as u32;
+        for shape in &self.shapes {
+            let (shape_type, values) = match shape.shape {
+                CollShape::Box { min, max } => (0u8, [min.x, min.y, min.z, max.x, max.y, max.z, 0]),
+                CollShape::Sphere { center, radius } => (1, [center.x, center.y, center.z, radius, 0, 0, 0]),
+                CollShape::Capsule { a, b, radius } => (2, [a.x, a.y, a.z, b.x, b.y, b.z, radius]),
+            };
+            binary_section.push(shape_type);
+            binary_section.push(shape.terrain_id);
+            binary_section.extend_from_slice(&0u16.to_le_bytes());
+            for value in values {
+                binary_section.extend_from_slice(&value.to_le_bytes());
+            }
+        }
 
-        binary_section.extend_from_slice(&(self.nav_graph_nodes.len() as u16).to_le_bytes());
-        for node in &self.nav_graph_nodes {
-            binary_section.extend_from_slice(&node.pos_x.to_le_bytes());
-            binary_section.extend_from_slice(&node.pos_y.to_le_bytes());
-            binary_section.extend_from_slice(&node.pos_z.to_le_bytes());
-            binary_section.extend_from_slice(&node.neighbors[0].to_le_bytes());
-            binary_section.extend_from_slice(&node.neighbors[1].to_le_bytes());
-            binary_section.extend_from_slice(&node.neighbors[2].to_le_bytes());
-            binary_section.extend_from_slice(&node.neighbors[3].to_le_bytes());
+        // Gameplay objects, followed by their names. Each name is its length as a 32-bit value and the bytes, padded to 4 bytes
+        let gameplay_objects_offset = binary_section.len() as u32;
+        let mut names = Vec::<u8>::new();
+        let names_offset = binary_section.len() + self.gameplay_objects.len() * GAMEPLAY_OBJECT_SIZE;
+        for object in &self.gameplay_objects {
+            binary_section.push(object.kind as u8);
+            binary_section.extend_from_slice(&[0; 3]);
+            for value in [object.bounds.min, object.bounds.max] {
+                binary_section.extend_from_slice(&value.x.to_le_bytes());
+                binary_section.extend_from_slice(&value.y.to_le_bytes());
+                binary_section.extend_from_slice(&value.z.to_le_bytes());
+            }
+            binary_section.extend_from_slice(&((names_offset + names.len()) as u32).to_le_bytes());
+            names.extend_from_slice(&(object.name.len() as u32).to_le_bytes());
+            names.extend_from_slice(object.name.as_bytes());
+            while !names.len().is_multiple_of(4) {
+                names.push(0);
+            }
         }
+        binary_section.extend_from_slice(&names);
 
-        // Open output file
-        let mut file = File::create(output_col).unwrap();
+        // Triangle planes, only if the flag is set
+        let triangle_planes_offset = binary_section.len() as u32;
+        if self.precomputed_planes {
+            for triangle in &self.triangles {
+                let planes = triangle.planes();
+                binary_section.extend_from_slice(&planes.distance.to_le_bytes());
+                for (normal, distance) in planes.edge_normals.iter().zip(planes.edge_distances) {
+                    binary_section.extend_from_slice(&normal.x.to_le_bytes());
+                    binary_section.extend_from_slice(&normal.y.to_le_bytes());
+                    binary_section.extend_from_slice(&normal.z.to_le_bytes());
+                    binary_section.extend_from_slice(&distance.to_le_bytes());
+                }
+            }
+        }
 
         // Write file magic
-        validate(file.write("FCOL".as_bytes()));
+        file.write_all("FCOL".as_bytes())?;
 
         // Write header
-        let n_verts = self.triangles.len() as u32 * 3;
-        let n_nodes = self.nodes.len() as u32 * 3;
-        validate(file.write(&n_verts.to_le_bytes()));
-        validate(file.write(&n_nodes.to_le_bytes()));
-        validate(file.write(&triangle_data_offset.to_le_bytes()));
-        validate(file.write(&terrain_id_offset.to_le_bytes()));
-        validate(file.write(&bvh_nodes_offset.to_le_bytes()));
-        validate(file.write(&bvh_indices_offset.to_le_bytes()));
-        validate(file.write(&nav_graph_offset.to_le_bytes()));
+        file.write_all(&FCOL_VERSION.to_le_bytes())?;
+        file.write_all(&flags.to_le_bytes())?;
+        file.write_all(&(self.triangles.len() as u32).to_le_bytes())?;
+        file.write_all(&(self.nodes.len() as u32).to_le_bytes())?;
+        file.write_all(&(self.indices.len() as u32).to_le_bytes())?;
+        file.write_all(&(self.nav_graphs.len() as u32).to_le_bytes())?;
+        file.write_all(&triangle_data_offset.to_le_bytes())?;
+        file.write_all(&terrain_id_offset.to_le_bytes())?;
+        file.write_all(&bvh_nodes_offset.to_le_bytes())?;
+        file.write_all(&bvh_indices_offset.to_le_bytes())?;
+        file.write_all(&nav_graphs_offset.to_le_bytes())?;
+        file.write_all(&surface_type_offset.to_le_bytes())?;
+        file.write_all(&(self.navmesh_vertices.len() as u32).to_le_bytes())?;
+        file.write_all(&(self.navmesh_polygons.len() as u32).to_le_bytes())?;
+        file.write_all(&navmesh_vertices_offset.to_le_bytes())?;
+        file.write_all(&navmesh_polygons_offset.to_le_bytes())?;
+        file.write_all(&(self.shapes.len() as u32).to_le_bytes())?;
+        file.write_all(&shapes_offset.to_le_bytes())?;
+        file.write_all(&(self.gameplay_objects.len() as u32).to_le_bytes())?;
+        file.write_all(&gameplay_objects_offset.to_le_bytes())?;
+        file.write_all(&triangle_planes_offset.to_le_bytes())?;
 
         // Write binary section
-        validate(file.write(binary_section.as_slice()));
+        file.write_all(binary_section.as_slice())?;
+        Ok(())
     }
 }
 
+impl CollModelPSX {
+    pub fn load(path: &Path) -> Result<Self> {
+        Self::read(&std::fs::read(path)?)
+    }
+
+    pub fn read(data: &[u8]) -> Result<Self> {
+        let header = CollHeader::read(data)?;
+        header.verify(data.len())?;
+        let n_triangles = header.n_triangles;
+        let n_nodes = header.n_nodes;
+        let wide_indices = header.wide_indices();
+        let read_index = |reader: &mut Reader, what: &'static str| -> Result<u32> {
+            match wide_indices {
+                true => reader.u32(what),
+                false => reader.u16(what).map(|index| index as u32),
+            }
+        };
+
+        // Triangle data
+        let mut triangles = Vec::with_capacity(n_triangles);
+        let mut reader = Reader::new(data, header.triangle_data_offset);
+        let mut terrain_ids = Reader::new(data, header.terrain_id_offset);
+        let mut surface_types = Reader::new(data, header.surface_type_offset);
+        for i in 0..n_triangles {
+            let v0 = reader.ivec3("triangle data")?;
+            let v1 = reader.ivec3("triangle data")?;
+            let v2 = reader.ivec3("triangle data")?;
+            let normal = reader.ivec3("triangle data")?;
+            let terrain_id = terrain_ids.u8("terrain IDs")?;
+            let surface_type = surface_types.u8("surface types")?;
+            let surface_type = SurfaceType::from_u8(surface_type).ok_or_else(|| Error::InvalidData {
+                what: "surface types",
+                reason: format!("triangle {i} has unknown surface type {surface_type}"),
+            })?;
+            triangles.push(CollTrianglePSX {
+                v0,
+                v1,
+                v2,
+                normal,
+                terrain_id,
+                surface_type,
+            });
+        }
+
+        // BVH nodes
+        let mut nodes = Vec::with_capacity(n_nodes);
+        let mut reader = Reader::new(data, header.bvh_nodes_offset);
+        for _ in 0..n_nodes {
+            nodes.push(BvhNode {
+                bounds: Aabb {
+                    min: reader.ivec3("BVH nodes")?,
+                    max: reader.ivec3("BVH nodes")?,
+                },
+                left_first: read_index(&mut reader, "BVH nodes")?,
+                primitive_count: read_index(&mut reader, "BVH nodes")?,
+            });
+        }
+
+        // BVH indices
+        let mut indices = Vec::with_capacity(header.n_indices);
+        let mut reader = Reader::new(data, header.bvh_indices_offset);
+        for _ in 0..header.n_indices {
+            let index = read_index(&mut reader, "BVH indices")?;
+            if index as usize >= n_triangles + header.n_shapes {
+                return Err(Error::InvalidData {
+                    what: "BVH indices",
+                    reason: format!("index {index} points past the {n_triangles} triangles and {} shapes", header.n_shapes),
+                });
+            }
+            indices.push(index);
+        }
+
+        for (i, node) in nodes.iter().enumerate() {
+            let (start, count, len) = match node.primitive_count {
+                0 => (node.left_first as usize, 2, nodes.len()),
+                n => (node.left_first as usize, n as usize, indices.len()),
+            };
+            if start + count > len {
+                return Err(Error::InvalidData {
+                    what: "BVH nodes",
+                    reason: format!("node {i} references elements {start}..{} of {len}", start + count),
+                });
+            }
+        }
+
+        // Navigation graphs
+        let mut table = Reader::new(data, header.nav_graphs_offset);
+        let mut nav_graphs = Vec::with_capacity(header.n_nav_graphs);
+        for graph_index in 0..header.n_nav_graphs {
+            let n_nodes = table.u32("navigation graphs")? as usize;
+            let n_links = table.u32("navigation graphs")? as usize;
+            let mut nodes_reader = Reader::new(data, table.offset(COL_HEADER_SIZE, 4, "navigation graph nodes")?);
+            let links_offset = table.offset(COL_HEADER_SIZE, 4, "navigation graph links")?;
+            let link_types_offset = table.offset(COL_HEADER_SIZE, 4, "navigation graph link types")?;
+            let agent_height = table.i32("navigation graphs")?;
+            let agent_radius = table.i32("navigation graphs")?;
+            let max_link_distance = table.i32("navigation graphs")?;
+            let max_neighbors = table.u32("navigation graphs")?;
+            let step_height = table.i32("navigation graphs")?;
+            let max_drop = table.i32("navigation graphs")?;
+            let max_jump_distance = table.i32("navigation graphs")?;
+
+            let mut nodes = Vec::with_capacity(n_nodes);
+            for _ in 0..n_nodes {
+                let pos_x = nodes_reader.i16("navigation graph nodes")?;
+                let pos_y = nodes_reader.i16("navigation graph nodes")?;
+                let pos_z = nodes_reader.i16("navigation graph nodes")?;
+                let n_neighbors = nodes_reader.u16("navigation graph nodes")? as usize;
+                let first_link = nodes_reader.u32("navigation graph nodes")? as usize;
+                if first_link + n_neighbors > n_links {
+                    return Err(Error::InvalidData {
+                        what: "navigation graph nodes",
+                        reason: format!("graph {graph_index} node {} uses links {first_link}..{} of {n_links}", nodes.len(), first_link + n_neighbors),
+                    });
+                }
+
+                let index_size = if wide_indices { 4 } else { 2 };
+                let mut links_reader = Reader::new(data, links_offset + first_link * index_size);
+                let mut link_types_reader = Reader::new(data, link_types_offset + first_link);
+                let mut neighbors = Vec::with_capacity(n_neighbors);
+                let mut link_types = Vec::with_capacity(n_neighbors);
+                for _ in 0..n_neighbors {
+                    let neighbor = read_index(&mut links_reader, "navigation graph links")?;
+                    if neighbor as usize >= n_nodes {
+                        return Err(Error::InvalidData {
+                            what: "navigation graph links",
+                            reason: format!("graph {graph_index} node {} links to node {neighbor}, but there are only {n_nodes} nodes", nodes.len()),
+                        });
+                    }
+                    neighbors.push(neighbor);
+
+                    let link_type = link_types_reader.u8("navigation graph link types")?;
+                    link_types.push(NavLinkType::from_u8(link_type).ok_or_else(|| Error::InvalidData {
+                        what: "navigation graph link types",
+                        reason: format!("graph {graph_index} node {} has unknown link type {link_type}", nodes.len()),
+                    })?);
+                }
+                nodes.push(NavGraphNode {
+                    pos_x,
+                    pos_y,
+                    pos_z,
+                    neighbors,
+                    link_types,
+                });
+            }
+            nav_graphs.push(NavGraph {
+                agent_height,
+                agent_radius,
+                max_link_distance,
+                max_neighbors,
+                step_height,
+                max_drop,
+                max_jump_distance,
+                nodes,
+            });
+        }
+
+        // Navmesh
+        let mut reader = Reader::new(data, header.navmesh_vertices_offset);
+        let mut navmesh_vertices = Vec::with_capacity(header.n_navmesh_vertices);
+        for _ in 0..header.n_navmesh_vertices {
+            navmesh_vertices.push(reader.ivec3("navmesh vertices")?);
+        }
+        let mut reader = Reader::new(data, header.navmesh_polygons_offset);
+        let mut navmesh_polygons = Vec::with_capacity(header.n_navmesh_polygons);
+        for i in 0..header.n_navmesh_polygons {
+            let n_vertices = reader.u8("navmesh polygons")? as usize;
+            let terrain_id = reader.u8("navmesh polygons")?;
+            reader.u16("navmesh polygons")?; // padding
+            let mut lists = [vec![], vec![]];
+            for list in &mut lists {
+                for _ in 0..NAVMESH_MAX_POLYGON_VERTICES {
+                    list.push(match read_index(&mut reader, "navmesh polygons")? {
+                        0xFFFF if !wide_indices => NAV_NO_NEIGHBOR,
+                        index => index,
+                    });
+                }
+                list.truncate(n_vertices);
+            }
+            let [vertices, neighbors] = lists;
+            let bad_vertex = vertices.iter().any(|&v| v as usize >= navmesh_vertices.len());
+            let bad_neighbor = neighbors.iter().any(|&n| n != NAV_NO_NEIGHBOR && n as usize >= header.n_navmesh_polygons);
+            if !(3..=NAVMESH_MAX_POLYGON_VERTICES).contains(&n_vertices) || bad_vertex || bad_neighbor {
+                return Err(Error::InvalidData {
+                    what: "navmesh polygons",
+                    reason: format!("polygon {i} has {n_vertices} vertices, or references vertices or polygons that don't exist"),
+                });
+            }
+            navmesh_polygons.push(NavMeshPolygon {
+                vertices,
+                neighbors,
+                terrain_id,
+            });
+        }
+
+        // Shapes
+        let mut reader = Reader::new(data, header.shapes_offset);
+        let mut shapes = Vec::with_capacity(header.n_shapes);
+        for i in 0..header.n_shapes {
+            let shape_type = reader.u8("shapes")?;
+            let terrain_id = reader.u8("shapes")?;
+            reader.u16("shapes")?; // padding
+            let mut values = [0i32; 7];
+            for value in &mut values {
+                *value = reader.i32("shapes")?;
+            }
+            let [x0, y0, z0, x1, y1, z1, w] = values;
+            let shape = match shape_type {
+                0 => CollShape::Box {
+                    min: glam::IVec3::new(x0, y0, z0),
+                    max: glam::IVec3::new(x1, y1, z1),
+                },
+                1 => CollShape::Sphere {
+                    center: glam::IVec3::new(x0, y0, z0),
+                    radius: x1,
+                },
+                2 => CollShape::Capsule {
+                    a: glam::IVec3::new(x0, y0, z0),
+                    b: glam::IVec3::new(x1, y1, z1),
+                    radius: w,
+                },
+                _ => {
+                    return Err(Error::InvalidData {
+                        what: "shapes",
+                        reason: format!("shape {i} has unknown type {shape_type}"),
+                    })
+                }
+            };
+            shapes.push(CollShapePSX { shape, terrain_id });
+        }
+
+        // Gameplay objects
+        let mut reader = Reader::new(data, header.gameplay_objects_offset);
+        let mut gameplay_objects = Vec::with_capacity(header.n_gameplay_objects);
+        for i in 0..header.n_gameplay_objects {
+            let kind = reader.u8("gameplay objects")?;
+            let kind = GameplayObjectKind::from_u8(kind).ok_or_else(|| Error::InvalidData {
+                what: "gameplay objects",
+                reason: format!("object {i} has unknown kind {kind}"),
+            })?;
+            reader.bytes(3, "gameplay objects")?; // padding
+            let bounds = Aabb {
+                min: reader.ivec3("gameplay objects")?,
+                max: reader.ivec3("gameplay objects")?,
+            };
+            let mut names = Reader::new(data, reader.offset(COL_HEADER_SIZE, 4, "gameplay object names")?);
+            let name_len = names.u32("gameplay object names")? as usize;
+            let name = String::from_utf8(names.bytes(name_len, "gameplay object names")?.to_vec()).map_err(|_| Error::InvalidData {
+                what: "gameplay object names",
+                reason: format!("name of object {i} is not valid UTF-8"),
+            })?;
+            gameplay_objects.push(GameplayObjectPSX { kind, name, bounds });
+        }
+
+        Ok(Self {
+            triangles,
+            shapes,
+            nodes,
+            indices,
+            nav_graphs,
+            navmesh_vertices,
+            navmesh_polygons,
+            gameplay_objects,
+            wide_indices,
+            precomputed_planes: header.precomputed_planes(),
+        })
+    }
+}
+
+#[derive(Debug, PartialEq)]
 pub struct MeshPSX {
     pub verts: Vec<VertexPSX>,
     pub n_triangles: usize,
@@ -145,11 +814,12 @@ pub struct MeshPSX {
     pub name: String,
 }
 
+#[derive(Debug, Default, PartialEq)]
 pub struct ModelPSX {
     pub meshes: Vec<MeshPSX>,
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct MeshDesc {
     pub vertex_start: u16,
     pub n_triangles: u16,
@@ -163,11 +833,25 @@ pub struct MeshDesc {
     pub _pad: i16,
 }
 
+#[derive(Debug, Default, PartialEq)]
 pub struct TextureCollectionPSX {
     pub texture_cells: Vec<TextureCellPSX>,
     pub texture_names: Vec<String>,
 }
 
+/// A texture cell descriptor as it is stored in the file
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TextureCellDesc {
+    pub sector_offset: u8,
+    pub palette_index: u8,
+    pub texture_width: u8,
+    pub texture_height: u8,
+    pub texture_bpp: u8,
+    pub n_palettes: u8,
+    pub avg_color: u32,
+}
+
+#[derive(Debug, PartialEq)]
 pub struct TextureCellPSX {
     pub texture_data: Vec<u8>,
     pub palette: Vec<u16>,
@@ -200,7 +884,7 @@ impl CollVertexPSX {
         bytes.extend(self.pos_x.to_le_bytes());
         bytes.extend(self.pos_y.to_le_bytes());
         bytes.extend(self.pos_z.to_le_bytes());
-        bytes.extend(self._terrain_id.to_le_bytes());
+        bytes.extend(self.terrain_id.to_le_bytes());
         bytes
     }
 }
@@ -210,12 +894,19 @@ impl ModelPSX {
         ModelPSX { meshes: Vec::new() }
     }
 
-    pub fn save(&self, path: &Path) -> std::io::Result<usize> {
-        // Create binary array of data
-        let mut raw_vertex_data = Vec::<VertexPSX>::new();
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        self.write(&mut file)?;
+        file.flush()?;
+        Ok(())
+    }
+
+    /// Calculates the mesh descriptors as they will be stored in the file
+    pub fn mesh_descs(&self) -> Result<Vec<MeshDesc>> {
+        let mut n_vertices = 0;
         let mut mesh_descs = Vec::<MeshDesc>::new();
 
-        // For each submesh, add the vertices to the array, and store 32-bit offsets to the start of each of them
+        // For each submesh, store the offset to the first vertex and the bounding box
         for mesh in self.meshes.as_slice() {
             // Find AABB extremes
             let mut x_max = -32768;
@@ -233,8 +924,16 @@ impl ModelPSX {
                 z_min = z_min.min(vertex.pos_z);
             }
 
+            if n_vertices + mesh.verts.len() > u16::MAX as usize {
+                return Err(Error::TooManyElements {
+                    what: "vertices",
+                    count: n_vertices + mesh.verts.len(),
+                    max: u16::MAX as usize,
+                });
+            }
+
             mesh_descs.push(MeshDesc {
-                vertex_start: raw_vertex_data.len() as u16,
+                vertex_start: n_vertices as u16,
                 n_triangles: mesh.n_triangles as u16,
                 n_quads: mesh.n_quads as u16,
                 x_min,
@@ -245,13 +944,17 @@ impl ModelPSX {
                 z_max,
                 _pad: 0,
             });
-            for vertex in &mesh.verts {
-                raw_vertex_data.push(*vertex);
-            }
+            n_vertices += mesh.verts.len();
         }
 
-        // Open output file
-        let mut file = File::create(path)?;
+        Ok(mesh_descs)
+    }
+
+    pub fn write<W: Write>(&self, file: &mut W) -> Result<()> {
+        // Create binary array of data
+        let mesh_descs = self.mesh_descs()?;
+        let raw_vertex_data: Vec<VertexPSX> = self.meshes.iter().flat_map(|mesh| mesh.verts.iter().copied()).collect();
+
         let mut raw_data = Vec::<u8>::new();
 
         // Mesh descs
@@ -270,7 +973,7 @@ impl ModelPSX {
         }
 
         // Align to word
-        while raw_data.len() % 4 != 0 {
+        while !raw_data.len().is_multiple_of(4) {
             raw_data.push(0);
         }
 
@@ -281,7 +984,7 @@ impl ModelPSX {
         }
 
         // Align to word
-        while raw_data.len() % 4 != 0 {
+        while !raw_data.len().is_multiple_of(4) {
             raw_data.push(0);
         }
 
@@ -295,7 +998,7 @@ impl ModelPSX {
         }
 
         // Align to word
-        while raw_data.len() % 4 != 0 {
+        while !raw_data.len().is_multiple_of(4) {
             raw_data.push(0);
         }
 
@@ -307,17 +1010,106 @@ impl ModelPSX {
         }
 
         // Write everything
-        validate(file.write("FMSH".as_bytes())); // file_magic
-        validate(file.write(&(self.meshes.len() as u32).to_le_bytes())); //n_submeshes
-        validate(file.write(&(offset_mesh_desc as u32).to_le_bytes()));
-        validate(file.write(&(offset_vertex_data as u32).to_le_bytes()));
-        validate(file.write(&(offset_mesh_names as u32).to_le_bytes()));
-        validate(file.write(&(offset_vertex_normals as u32).to_le_bytes()));
-        validate(file.write(&(0xFFFFFFFFu32).to_le_bytes())); // offset_lightmap_uv, will be filled by another tool
-        validate(file.write(&(0xFFFFFFFFu32).to_le_bytes())); // offset_lightmap_tex
-        validate(file.write(raw_data.as_slice()));
-
-        Ok(0)
+        file.write_all("FMSH".as_bytes())?; // file_magic
+        file.write_all(&(self.meshes.len() as u32).to_le_bytes())?; //n_submeshes
+        file.write_all(&(offset_mesh_desc as u32).to_le_bytes())?;
+        file.write_all(&(offset_vertex_data as u32).to_le_bytes())?;
+        file.write_all(&(offset_mesh_names as u32).to_le_bytes())?;
+        file.write_all(&(offset_vertex_normals as u32).to_le_bytes())?;
+        file.write_all(&(0xFFFFFFFFu32).to_le_bytes())?; // offset_lightmap_uv, will be filled by another tool
+        file.write_all(&(0xFFFFFFFFu32).to_le_bytes())?; // offset_lightmap_tex
+        file.write_all(raw_data.as_slice())?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        Self::read(&std::fs::read(path)?)
+    }
+
+    /// Reads only the mesh descriptors, exactly as they are stored in the file
+    pub fn read_mesh_descs(data: &[u8]) -> Result<Vec<MeshDesc>> {
+        let mut header = Reader::new(data, 0);
+        header.magic("FMSH")?;
+        let n_submeshes = header.u32("submesh count")? as usize;
+        let offset_mesh_desc = header.offset(MSH_HEADER_SIZE, 4, "mesh descriptors")?;
+
+        let mut reader = Reader::new(data, offset_mesh_desc);
+        let mut mesh_descs = Vec::new();
+        for _ in 0..n_submeshes {
+            mesh_descs.push(MeshDesc {
+                vertex_start: reader.u16("mesh descriptors")?,
+                n_triangles: reader.u16("mesh descriptors")?,
+                n_quads: reader.u16("mesh descriptors")?,
+                x_min: reader.i16("mesh descriptors")?,
+                x_max: reader.i16("mesh descriptors")?,
+                y_min: reader.i16("mesh descriptors")?,
+                y_max: reader.i16("mesh descriptors")?,
+                z_min: reader.i16("mesh descriptors")?,
+                z_max: reader.i16("mesh descriptors")?,
+                _pad: reader.i16("mesh descriptors")?,
+            });
+        }
+        Ok(mesh_descs)
+    }
+
+    pub fn read(data: &[u8]) -> Result<Self> {
+        let mesh_descs = Self::read_mesh_descs(data)?;
+
+        let mut header = Reader::new(data, 12);
+        let offset_vertex_data = header.offset(MSH_HEADER_SIZE, 4, "vertex data")?;
+        let offset_mesh_names = header.offset(MSH_HEADER_SIZE, 4, "mesh names")?;
+        let offset_vertex_normals = header.offset(MSH_HEADER_SIZE, 4, "vertex normals")?;
+
+        let mut vertices = Reader::new(data, offset_vertex_data);
+        let mut normals = Reader::new(data, offset_vertex_normals);
+        let mut names = Reader::new(data, offset_mesh_names);
+        let mut meshes = Vec::new();
+        let mut n_vertices = 0;
+        for (i, desc) in mesh_descs.iter().enumerate() {
+            // Submeshes are stored back to back, so the vertex offsets have to add up
+            if desc.vertex_start as usize != n_vertices {
+                return Err(Error::InvalidData {
+                    what: "mesh descriptors",
+                    reason: format!("submesh {i} starts at vertex {}, expected {n_vertices}", desc.vertex_start),
+                });
+            }
+            let n_verts = desc.n_triangles as usize * 3 + desc.n_quads as usize * 4;
+            n_vertices += n_verts;
+
+            let mut verts = Vec::with_capacity(n_verts);
+            for _ in 0..n_verts {
+                verts.push(VertexPSX {
+                    pos_x: vertices.i16("vertex data")?,
+                    pos_y: vertices.i16("vertex data")?,
+                    pos_z: vertices.i16("vertex data")?,
+                    color_r: vertices.u8("vertex data")?,
+                    color_g: vertices.u8("vertex data")?,
+                    color_b: vertices.u8("vertex data")?,
+                    tex_u: vertices.u8("vertex data")?,
+                    tex_v: vertices.u8("vertex data")?,
+                    texture_id: vertices.u8("vertex data")?,
+                    normal_x: normals.i8("vertex normals")?,
+                    normal_y: normals.i8("vertex normals")?,
+                    normal_z: normals.i8("vertex normals")?,
+                });
+                normals.u8("vertex normals")?;
+            }
+
+            let name_len = names.u32("mesh names")? as usize;
+            let name = String::from_utf8(names.bytes(name_len, "mesh names")?.to_vec()).map_err(|_| Error::InvalidData {
+                what: "mesh names",
+                reason: format!("name of submesh {i} is not valid UTF-8"),
+            })?;
+
+            meshes.push(MeshPSX {
+                verts,
+                n_triangles: desc.n_triangles as usize,
+                n_quads: desc.n_quads as usize,
+                name,
+            });
+        }
+
+        Ok(Self { meshes })
     }
 }
 
@@ -329,21 +1121,36 @@ impl TextureCollectionPSX {
         }
     }
 
-    pub fn save(&self, path: &Path) -> std::io::Result<usize> {
-        // Open output file
-        let mut file = File::create(path)?;
+    /// Looks up the texture ID of a texture by its name
+    pub fn texture_id(&self, name: &str) -> Option<usize> {
+        self.texture_names.iter().position(|texture_name| texture_name == name)
+    }
+
+    /// Looks up the name of a texture by its ID, if it has one
+    pub fn texture_name(&self, id: usize) -> Option<&str> {
+        self.texture_names.get(id).map(String::as_str).filter(|name| !name.is_empty())
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        self.write(&mut file)?;
+        file.flush()?;
+        Ok(())
+    }
 
+    pub fn write<W: Write>(&self, file: &mut W) -> Result<()> {
         // Write file magic
-        validate(file.write("FTXC".as_bytes()));
+        file.write_all("FTXC".as_bytes())?;
 
         // Write number of texture cells and palettes
-        validate(file.write(&(self.texture_cells.len() as u32).to_le_bytes()));
+        file.write_all(&(self.texture_cells.len() as u32).to_le_bytes())?;
 
         // Create binary data buffers for each part
         let mut bin_texture_cell_descs: Vec<u8> = Vec::new();
         let mut bin_palettes: Vec<u8> = Vec::new();
         let mut bin_palette_offsets: Vec<u8> = Vec::new();
         let mut bin_texture_data: Vec<u8> = Vec::new();
+        let mut bin_names: Vec<u8> = Vec::new();
 
         // Populate these buffers
         for i in 0..self.texture_cells.len() {
@@ -377,7 +1184,15 @@ impl TextureCollectionPSX {
                 bin_texture_data.extend(&cell.texture_data);
 
                 // Write texture offset
-                bin_texture_cell_descs.push(((curr_position + n_bytes_to_add) / 2048) as u8);
+                let sector = (curr_position + n_bytes_to_add) / 2048;
+                if sector > u8::MAX as u32 {
+                    return Err(Error::TooManyElements {
+                        what: "sectors of texture data",
+                        count: sector as usize + 1,
+                        max: u8::MAX as usize + 1,
+                    });
+                }
+                bin_texture_cell_descs.push(sector as u8);
 
                 // Write palette index
                 bin_texture_cell_descs.extend_from_slice(&(i as u8).to_le_bytes());
@@ -393,47 +1208,182 @@ impl TextureCollectionPSX {
                 // Write texture dimensions
                 bin_texture_cell_descs.extend_from_slice(&cell.avg_color.to_le_bytes());
             }
+
+            // Texture name, cells without a name get an empty one so the table always has one entry per cell
+            let name = self.texture_names.get(i).map(String::as_str).unwrap_or_default();
+            bin_names.extend_from_slice(&(name.len() as u32).to_le_bytes());
+            bin_names.extend_from_slice(name.as_bytes());
+        }
+
+        // Align to word
+        while !bin_names.len().is_multiple_of(4) {
+            bin_names.push(0);
         }
 
         // I guess we can just write these in any order at this point, since the offsets will be stored in the main header
         let mut cursor: u32 = 0;
 
         // Write offset to texture cell descs
-        validate(file.write(&(cursor).to_le_bytes()));
+        file.write_all(&(cursor).to_le_bytes())?;
         cursor += bin_texture_cell_descs.len() as u32;
 
         // Write offset to palettes offsets
-        validate(file.write(&(cursor).to_le_bytes()));
+        file.write_all(&(cursor).to_le_bytes())?;
         cursor += bin_palette_offsets.len() as u32;
 
         // Write offset to palettes
-        validate(file.write(&(cursor).to_le_bytes()));
+        file.write_all(&(cursor).to_le_bytes())?;
         cursor += bin_palettes.len() as u32;
 
+        // The name table goes right after the palettes, so the texture data stays at the end of the file
+        let offset_names = cursor;
+        cursor += bin_names.len() as u32;
+
         // Align the texture data to a CD sector. This allows for some neat optimizations
-        let real_cursor = cursor + 24;
+        let real_cursor = cursor + TXC_HEADER_SIZE as u32;
         let bytes_to_pad = ((real_cursor + 2047) & !2047) - real_cursor;
         cursor += bytes_to_pad;
 
         // Write offset to textures
-        validate(file.write(&(cursor).to_le_bytes()));
+        file.write_all(&(cursor).to_le_bytes())?;
         //cursor += bin_texture_data.len() as u32;
 
-        // todo: name table
-        validate(file.write(&(0u32).to_le_bytes()));
+        // Write offset to names
+        file.write_all(&(offset_names).to_le_bytes())?;
 
         // Write the raw buffers now, in the right order
-        validate(file.write(bin_texture_cell_descs.as_slice()));
-        validate(file.write(bin_palette_offsets.as_slice()));
-        validate(file.write(bin_palettes.as_slice()));
+        file.write_all(bin_texture_cell_descs.as_slice())?;
+        file.write_all(bin_palette_offsets.as_slice())?;
+        file.write_all(bin_palettes.as_slice())?;
+        file.write_all(bin_names.as_slice())?;
 
         // Pad with zeroes
         for _ in 0..bytes_to_pad {
-            validate(file.write(&[0u8]));
+            file.write_all(&[0u8])?;
         }
 
-        validate(file.write(bin_texture_data.as_slice()));
+        file.write_all(bin_texture_data.as_slice())?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        Self::read(&std::fs::read(path)?)
+    }
+
+    /// Reads only the texture cell descriptors, exactly as they are stored in the file
+    pub fn read_cell_descs(data: &[u8]) -> Result<Vec<TextureCellDesc>> {
+        let mut header = Reader::new(data, 0);
+        header.magic("FTXC")?;
+        let n_cells = header.u32("texture cell count")? as usize;
+        let offset_cell_descs = header.offset(TXC_HEADER_SIZE, 4, "texture cell descriptors")?;
+
+        let mut descs = Reader::new(data, offset_cell_descs);
+        let mut cell_descs = Vec::new();
+        for _ in 0..n_cells {
+            let sector_offset = descs.u8("texture cell descriptors")?;
+            let palette_index = descs.u8("texture cell descriptors")?;
+            let texture_width = descs.u8("texture cell descriptors")?;
+            let texture_height = descs.u8("texture cell descriptors")?;
+            let texture_bpp = descs.u8("texture cell descriptors")?;
+            let n_palettes = descs.u8("texture cell descriptors")?;
+            descs.bytes(2, "texture cell descriptors")?; // padding
+            let avg_color = descs.u32("texture cell descriptors")?;
+            cell_descs.push(TextureCellDesc {
+                sector_offset,
+                palette_index,
+                texture_width,
+                texture_height,
+                texture_bpp,
+                n_palettes,
+                avg_color,
+            });
+        }
+        Ok(cell_descs)
+    }
+
+    pub fn read(data: &[u8]) -> Result<Self> {
+        let cell_descs = Self::read_cell_descs(data)?;
+
+        let mut header = Reader::new(data, 12);
+        let offset_palette_offsets = header.offset(TXC_HEADER_SIZE, 4, "palette offsets")?;
+        let offset_palettes = header.offset(TXC_HEADER_SIZE, 2, "palettes")?;
+        let offset_textures = header.offset(TXC_HEADER_SIZE, 4, "texture data")?;
+        let offset_names = header.offset(TXC_HEADER_SIZE, 4, "texture names")?;
+
+        // Texture data starts at a CD sector, except in files from before the name table, which padded as if the header was 24 bytes
+        if offset_textures % 2048 != 0 && offset_textures % 2048 != TXC_HEADER_SIZE - 24 {
+            return Err(Error::Misaligned {
+                what: "texture data",
+                offset: offset_textures,
+                alignment: 2048,
+            });
+        }
+
+        // Older files didn't have a name table and stored 0 here, which would point at the cell descriptors
+        let mut names = match offset_names == TXC_HEADER_SIZE && !cell_descs.is_empty() {
+            true => None,
+            false => Some(Reader::new(data, offset_names)),
+        };
+
+        let mut texture_cells = Vec::with_capacity(cell_descs.len());
+        let mut texture_names = Vec::new();
+        for (i, desc) in cell_descs.into_iter().enumerate() {
+            let sector = desc.sector_offset as usize;
+            let palette_index = desc.palette_index as usize;
+            let texture_width = desc.texture_width;
+            let texture_height = desc.texture_height;
+            let texture_bpp = desc.texture_bpp as i32;
+            let n_palettes = desc.n_palettes as usize;
+            let avg_color = desc.avg_color;
+
+            if !matches!(texture_bpp, 4 | 8 | 15) {
+                return Err(Error::InvalidData {
+                    what: "texture cell descriptors",
+                    reason: format!("unsupported bit depth {texture_bpp}"),
+                });
+            }
+
+            // Palettes, 15-bit direct color textures don't have any
+            let mut palette_offset = Reader::new(data, offset_palette_offsets + palette_index * 4);
+            let palette_offset = palette_offset.u32("palette offsets")? as usize;
+            let mut palettes = Reader::new(data, offset_palettes + palette_offset);
+            let mut palette = Vec::new();
+            for _ in 0..(n_palettes << texture_bpp) {
+                palette.push(palettes.u16("palettes")?);
+            }
+
+            // Texture data, a size of 0 means 256
+            let width = if texture_width == 0 { 256 } else { texture_width as usize };
+            let height = if texture_height == 0 { 256 } else { texture_height as usize };
+            let mut texture = Reader::new(data, offset_textures + sector * 2048);
+            let texture_size = match texture_bpp {
+                15 => width * height * 2,
+                bpp => width * height * bpp as usize / 8,
+            };
+            let texture_data = texture.bytes(texture_size, "texture data")?.to_vec();
+
+            texture_cells.push(TextureCellPSX {
+                texture_data,
+                palette,
+                texture_width,
+                texture_height,
+                avg_color,
+                texture_bpp,
+            });
+
+            if let Some(names) = &mut names {
+                let name_len = names.u32("texture names")? as usize;
+                let name = String::from_utf8(names.bytes(name_len, "texture names")?.to_vec()).map_err(|_| Error::InvalidData {
+                    what: "texture names",
+                    reason: format!("name of texture {i} is not valid UTF-8"),
+                })?;
+                texture_names.push(name);
+            }
+        }
 
-        Ok(0)
+        Ok(Self {
+            texture_cells,
+            texture_names,
+        })
     }
 }
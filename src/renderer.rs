@@ -1,15 +1,76 @@
-use std::{ffi::c_void, mem::size_of};
+#[cfg(feature = "gpu")]
+use std::{ffi::c_void, mem::{size_of, size_of_val}};
 
+#[cfg(feature = "gpu")]
 use gl::types::{GLenum, GLfloat, GLvoid};
+#[cfg(feature = "gpu")]
 use glfw::{Glfw, PWindow};
+#[cfg(feature = "gpu")]
 use log::error;
+use log::info;
+#[cfg(feature = "gpu")]
 use memoffset::offset_of;
 
 use crate::psx_structs::CollVertexPSX;
 
 const RESOLUTION: u32 = 32;
 
-pub struct Renderer {
+/// Answers line-of-sight queries for the nav graph, either on the GPU or on the CPU
+pub enum Renderer {
+    #[cfg(feature = "gpu")]
+    Gpu(GpuRenderer),
+    Software(SoftwareRenderer),
+}
+
+impl Renderer {
+    /// Creates the OpenGL renderer if possible, and falls back to the software rasterizer otherwise
+    pub fn new(force_software: bool) -> Self {
+        #[cfg(feature = "gpu")]
+        if !force_software {
+            if let Some(renderer) = GpuRenderer::new() {
+                return Renderer::Gpu(renderer);
+            }
+            info!("no OpenGL 4.6 context available, falling back to the software rasterizer");
+        }
+
+        #[cfg(not(feature = "gpu"))]
+        if !force_software {
+            info!("built without the \"gpu\" feature, using the software rasterizer");
+        }
+
+        Renderer::Software(SoftwareRenderer::new())
+    }
+
+    pub fn upload_mesh(&mut self, vertices: &[CollVertexPSX]) {
+        match self {
+            #[cfg(feature = "gpu")]
+            Renderer::Gpu(renderer) => renderer.upload_mesh_to_gpu(vertices),
+            Renderer::Software(renderer) => renderer.upload_mesh(vertices),
+        }
+    }
+
+    pub fn is_path_occupied(&mut self, from_position: glam::Vec3, to_target: glam::Vec3, ray_width: f32) -> bool {
+        match self {
+            #[cfg(feature = "gpu")]
+            Renderer::Gpu(renderer) => renderer.is_path_occupied(from_position, to_target, ray_width),
+            Renderer::Software(renderer) => renderer.is_path_occupied(from_position, to_target, ray_width),
+        }
+    }
+}
+
+/// Builds the same view and projection matrix for both renderers, so they agree on what "occupied" means.
+/// The projection maps depth from [0, distance_to_target * 2.0] to [0, 1], so the target sits at depth 0.5
+/// and the path is occupied when anything ends up in [0, 0.5]
+fn path_matrix(from_position: glam::Vec3, to_target: glam::Vec3, ray_width: f32) -> glam::Mat4 {
+    let distance_to_target = from_position.distance(to_target);
+    let matrix_proj = glam::Mat4::orthographic_rh(-ray_width, ray_width, -ray_width, ray_width, 0.0, distance_to_target * 2.0);
+    // let matrix_proj = glam::Mat4::perspective_rh(PI / 2.0, 1.0, 0.01, 65536.0); // debug view
+    let matrix_view = glam::Mat4::look_at_rh(-from_position, -to_target, glam::Vec3::new(0.0, -1.0, 0.0)); // todo: check if this is correct
+    matrix_proj * matrix_view
+}
+
+#[cfg(feature = "gpu")]
+pub struct GpuRenderer {
     program: u32,
     vao: u32,
     vbo: u32,
@@ -18,16 +79,18 @@ pub struct Renderer {
     _glfw: Glfw,
 }
 
-impl Renderer {
-    pub fn new() -> Self {
+#[cfg(feature = "gpu")]
+impl GpuRenderer {
+    /// Returns `None` if no window with an OpenGL 4.6 context could be created, e.g. on a headless machine
+    pub fn new() -> Option<Self> {
         // Set up a basic OpenGL setup
-        let mut glfw = glfw::init(glfw::fail_on_errors).unwrap();
+        let mut glfw = glfw::init(glfw::log_errors).ok()?;
 
         // Create an invisible window
         glfw.window_hint(glfw::WindowHint::Visible(false));
-        let (window, _events) = glfw
-            .create_window(RESOLUTION, RESOLUTION, "title", glfw::WindowMode::Windowed)
-            .expect("Failed to create window.");
+        glfw.window_hint(glfw::WindowHint::ContextVersion(4, 6));
+        let (window, _events) =
+            glfw.create_window(RESOLUTION, RESOLUTION, "title", glfw::WindowMode::Windowed)?;
         glfw.make_context_current(Some(&window));
         glfw.set_swap_interval(glfw::SwapInterval::None);
 
@@ -36,7 +99,7 @@ impl Renderer {
         unsafe {
             let error = gl::GetError();
             if error != gl::NO_ERROR {
-                panic!();
+                return None;
             }
         }
         let program;
@@ -86,15 +149,19 @@ impl Renderer {
 
             gl::LinkProgram(program);
             gl::UseProgram(program);
+
+            // glam's projection matrices output depth in [0, 1] instead of OpenGL's default [-1, 1]. Without this,
+            // everything behind the source would end up in the first half of the depth buffer and count as occupied
+            gl::ClipControl(gl::LOWER_LEFT, gl::ZERO_TO_ONE);
         }
-        Self {
-            program: program,
+        Some(Self {
+            program,
             vao: 0,
             vbo: 0,
             n_vertices: 0,
             _window: window,
             _glfw: glfw,
-        }
+        })
     }
 
     pub fn load_shader_part(shader_type: GLenum, source: String, program: u32) {
@@ -132,7 +199,7 @@ impl Renderer {
         }
     }
 
-    pub fn upload_mesh_to_gpu(&mut self, vertices: &Vec<CollVertexPSX>) {
+    pub fn upload_mesh_to_gpu(&mut self, vertices: &[CollVertexPSX]) {
         // Upload the mesh to the GPU
         unsafe {
             // Generate buffers
@@ -155,7 +222,7 @@ impl Renderer {
             // Upload the buffer
             gl::BufferData(
                 gl::ARRAY_BUFFER,
-                (size_of::<CollVertexPSX>() * vertices.len()) as isize,
+                size_of_val(vertices) as isize,
                 &vertices[0] as *const CollVertexPSX as *const c_void,
                 gl::STATIC_DRAW,
             );
@@ -164,11 +231,7 @@ impl Renderer {
     }
 
     pub fn is_path_occupied(&mut self, from_position: glam::Vec3, to_target: glam::Vec3, ray_width: f32) -> bool {
-        let distance_to_target = from_position.distance(to_target);
-        let matrix_proj = glam::Mat4::orthographic_rh(-ray_width, ray_width, -ray_width, ray_width, 0.0, distance_to_target * 2.0);
-        // let matrix_proj = glam::Mat4::perspective_rh(PI / 2.0, 1.0, 0.01, 65536.0); // debug view
-        let matrix_view = glam::Mat4::look_at_rh(-from_position, -to_target, glam::Vec3::new(0.0, -1.0, 0.0)); // todo: check if this is correct
-        let matrix_combined = matrix_proj * matrix_view;
+        let matrix_combined = path_matrix(from_position, to_target, ray_width);
         let mut buffer = vec![0.0f32; (RESOLUTION * RESOLUTION) as usize];
         
         unsafe {
@@ -181,7 +244,7 @@ impl Renderer {
             gl::Enable(gl::DEPTH_TEST);
             gl::Enable(gl::CULL_FACE);
             gl::Viewport(0, 0, RESOLUTION as _, RESOLUTION as _);
-            gl::DrawArrays(gl::TRIANGLES, 0, self.n_vertices as i32);
+            gl::DrawArrays(gl::TRIANGLES, 0, self.n_vertices);
 
             // Get depth buffer to cpu
             gl::ReadPixels(0, 0, RESOLUTION as i32, RESOLUTION as i32, gl::DEPTH_COMPONENT, gl::FLOAT, buffer.as_mut_ptr() as *mut GLvoid);
@@ -190,8 +253,87 @@ impl Renderer {
         // find minimum depth in framebuffer
         let min_depth = buffer.into_iter().reduce(f32::min).unwrap_or(1.0);
 
-        // The orthographic projection matrix takes the depth from [0, distance_to_target * 2.0] to [0, 1], so we just gotta check if the value is smaller than or equal to 0.5
-        // So if the minimum depth is bigger than 0.5, the path from position to target is obstructed
-        return min_depth <= 0.5
+        // Depth is cleared to 1.0 and everything outside [0, 1] is clipped, so this is the same check as `SoftwareRenderer`
+        min_depth <= 0.5
+    }
+}
+
+/// Depth-only rasterizer that gives the same answers as `GpuRenderer`, for machines without a GPU
+#[derive(Default)]
+pub struct SoftwareRenderer {
+    triangles: Vec<[glam::Vec3; 3]>,
+}
+
+impl SoftwareRenderer {
+    pub fn new() -> Self {
+        Self { triangles: vec![] }
+    }
+
+    pub fn upload_mesh(&mut self, vertices: &[CollVertexPSX]) {
+        self.triangles = vertices
+            .chunks_exact(3)
+            .map(|triangle| {
+                [0, 1, 2].map(|i| {
+                    glam::vec3(triangle[i].pos_x as f32, triangle[i].pos_y as f32, triangle[i].pos_z as f32)
+                })
+            })
+            .collect();
+    }
+
+    pub fn is_path_occupied(&self, from_position: glam::Vec3, to_target: glam::Vec3, ray_width: f32) -> bool {
+        let matrix_combined = path_matrix(from_position, to_target, ray_width);
+
+        // Anything outside of this box can't end up in the first half of the depth range, so skip it early
+        let margin = glam::Vec3::splat(ray_width * 2.0);
+        let box_min = (-from_position).min(-to_target) - margin;
+        let box_max = (-from_position).max(-to_target) + margin;
+
+        for triangle in &self.triangles {
+            let tri_min = triangle[0].min(triangle[1].min(triangle[2]));
+            let tri_max = triangle[0].max(triangle[1].max(triangle[2]));
+            if tri_min.cmpgt(box_max).any() || tri_max.cmplt(box_min).any() {
+                continue;
+            }
+
+            // The projection is orthographic, so w is always 1 and the divide in `project_point3` doesn't change anything
+            let [v0, v1, v2] = triangle.map(|v| matrix_combined.project_point3(v));
+
+            // Cull back faces, same as the default OpenGL state
+            let area = (v1.x - v0.x) * (v2.y - v0.y) - (v2.x - v0.x) * (v1.y - v0.y);
+            if area <= 0.0 {
+                continue;
+            }
+
+            // Find the pixels this triangle could cover
+            let to_pixel = |ndc: f32| (ndc * 0.5 + 0.5) * RESOLUTION as f32;
+            let x_start = to_pixel(v0.x.min(v1.x.min(v2.x))).floor().max(0.0) as u32;
+            let x_end = (to_pixel(v0.x.max(v1.x.max(v2.x))).ceil().max(0.0) as u32).min(RESOLUTION);
+            let y_start = to_pixel(v0.y.min(v1.y.min(v2.y))).floor().max(0.0) as u32;
+            let y_end = (to_pixel(v0.y.max(v1.y.max(v2.y))).ceil().max(0.0) as u32).min(RESOLUTION);
+
+            for y in y_start..y_end {
+                for x in x_start..x_end {
+                    // Sample at the pixel center
+                    let px = ((x as f32 + 0.5) / RESOLUTION as f32) * 2.0 - 1.0;
+                    let py = ((y as f32 + 0.5) / RESOLUTION as f32) * 2.0 - 1.0;
+
+                    // Barycentric coordinates using edge functions
+                    let w0 = (v2.x - v1.x) * (py - v1.y) - (v2.y - v1.y) * (px - v1.x);
+                    let w1 = (v0.x - v2.x) * (py - v2.y) - (v0.y - v2.y) * (px - v2.x);
+                    let w2 = (v1.x - v0.x) * (py - v0.y) - (v1.y - v0.y) * (px - v0.x);
+                    if w0 < 0.0 || w1 < 0.0 || w2 < 0.0 {
+                        continue;
+                    }
+                    let depth = (w0 * v0.z + w1 * v1.z + w2 * v2.z) / area;
+
+                    // Only geometry between the source (depth 0) and the target (depth 0.5) blocks the path, like on the GPU
+                    if (0.0..=0.5).contains(&depth) {
+                        return true;
+                    }
+                }
+            }
+        }
+
+        false
     }
 }
@@ -5,37 +5,39 @@ use exoquant::{
     optimizer::{self, Optimizer},
     Color, SimpleColorSpace,
 };
-use log::{error, warn};
+use log::warn;
 
-use crate::psx_structs::{TextureCellPSX, TextureCollectionPSX};
+use crate::{
+    error::{Error, Result},
+    helpers::check_pixel_format,
+    psx_structs::{TextureCellPSX, TextureCollectionPSX},
+};
 
-pub fn txc_from_page(input: &Path) -> TextureCollectionPSX {
+pub fn txc_from_page(input: &Path) -> Result<TextureCollectionPSX> {
     // Open the image
+    let path = input.to_string_lossy().to_string();
     let image = match stb_image::image::load(input) {
-        stb_image::image::LoadResult::Error(_) => panic!(),
+        stb_image::image::LoadResult::Error(_) => return Err(Error::ImageDecode { path }),
         stb_image::image::LoadResult::ImageU8(data) => data,
-        stb_image::image::LoadResult::ImageF32(_) => todo!(),
+        stb_image::image::LoadResult::ImageF32(_) => {
+            return Err(Error::UnsupportedPixelFormat { path, format: "floating point".to_string() })
+        }
     };
 
     if image.height > 256 || image.width > 256 {
-        error!("Image can not be bigger than 256x256")
+        return Err(Error::ImageTooLarge { path, width: image.width, height: image.height });
     }
+    check_pixel_format(&path, image.depth)?;
 
     // Quantize it to 256 colours
     let mut tex_data_exoquant = Vec::new();
     let mut has_transparent_pixels = false;
     for pixel in image.data.chunks(image.depth) {
-        match image.depth {
-            4 => {
-                if pixel[3] == 0 {
-                    has_transparent_pixels = true;
-                    tex_data_exoquant.push(exoquant::Color::new(0, 0, 0, 0))
-                } else {
-                    tex_data_exoquant.push(exoquant::Color::new(pixel[0], pixel[1], pixel[2], 255))
-                }
-            }
-            3 => tex_data_exoquant.push(exoquant::Color::new(pixel[0], pixel[1], pixel[2], 255)),
-            _ => panic!(),
+        if image.depth == 4 && pixel[3] == 0 {
+            has_transparent_pixels = true;
+            tex_data_exoquant.push(exoquant::Color::new(0, 0, 0, 0))
+        } else {
+            tex_data_exoquant.push(exoquant::Color::new(pixel[0], pixel[1], pixel[2], 255))
         }
     }
     // Make half the histogram transparent pixels so that the quantizer actually generates a palette that contains one of those
@@ -88,9 +90,7 @@ pub fn txc_from_page(input: &Path) -> TextureCollectionPSX {
 
     if n_transparent_colors > 0 {
         // Swap the transparent and first colors
-        let temp = palette[0];
-        palette[0] = palette[transparent_index];
-        palette[transparent_index] = temp;
+        palette.swap(0, transparent_index);
 
         // Then remap the indices in the texture
         for pixel in &mut indexed_data {
@@ -129,7 +129,7 @@ pub fn txc_from_page(input: &Path) -> TextureCollectionPSX {
     let mut txc_psx = TextureCollectionPSX::new();
     txc_psx
         .texture_names
-        .push(String::from(input.file_name().unwrap().to_string_lossy()));
+        .push(input.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or(path));
     txc_psx.texture_cells.push(TextureCellPSX {
         texture_data: indexed_data,
         palette: tex_palette,
@@ -138,5 +138,5 @@ pub fn txc_from_page(input: &Path) -> TextureCollectionPSX {
         texture_bpp: 8,
         avg_color: 0,
     });
-    txc_psx
+    Ok(txc_psx)
 }
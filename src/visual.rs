@@ -10,56 +10,92 @@ use tobj::LoadOptions;
 
 use crate::{
     bsp::split_bsp,
+    error::{Error, Result},
+    helpers::{check_pixel_format, position_to_psx},
     kmeans::kmeans_cluster,
     psx_structs::{MeshPSX, ModelPSX, TextureCellPSX, TextureCollectionPSX, VertexPSX},
     MeshGridEntry,
 };
 
-pub fn obj2msh_txc(
-    input_obj: String,
-    output_msh: String,
-    output_txc: String,
-    using_texture_page: bool,
-    split: bool,
-) {
+#[derive(Clone, Default)]
+pub struct VisualSettings {
+    /// Whether textures default to 8-bit 256x256 texture pages or 4-bit 64x64 texture cells.
+    /// Individual textures can override this with a `psx_bpp` material parameter, or a `_4bpp`, `_8bpp` or `_15bpp` file name suffix
+    pub using_texture_page: bool,
+    /// Whether meshes should be split into smaller regions
+    pub split: bool,
+}
+
+/// Texture IDs from this value up are reserved for special materials like occluders
+const MAX_TEXTURES: usize = 128;
+
+pub fn obj2msh_txc(input_obj: String, output_msh: String, output_txc: String, settings: &VisualSettings) -> Result<()> {
     let (models, materials) = tobj::load_obj(
         &input_obj,
         &LoadOptions {
             single_index: true,
             ..Default::default()
         },
-    )
-    .expect("Failed to OBJ load file");
+    )?;
+    let materials = materials.unwrap_or_default();
+
+    let input_path = Path::new(&input_obj);
+    let texture_dir = input_path.parent().unwrap_or(Path::new(""));
+    let (model_psx, txc_psx) = convert_visual(&models, &materials, texture_dir, settings)?;
+
+    model_psx.save(Path::new(&output_msh))?;
+    txc_psx.save(Path::new(&output_txc))
+}
+
+/// Converts loaded OBJ data into a mesh and its texture collection. Texture paths in the materials are relative to `texture_dir`
+pub fn convert_visual(
+    models: &[tobj::Model],
+    materials: &[tobj::Material],
+    texture_dir: &Path,
+    settings: &VisualSettings,
+) -> Result<(ModelPSX, TextureCollectionPSX)> {
+    let using_texture_page = settings.using_texture_page;
 
     // Create a material mapping to filter out special material types like occluders
     let mut material_mapping = vec![];
-    let mut psx_id_tex_mapping = vec![];
-    if let Ok(materials_vec) = &materials {
-        for material in materials_vec {
-            // First let's figure out what we have in this material. Is it textured? Is it untextured? Is it an occluder?
-            let psx_tex_id;
-
-            if material.name.contains("occlude") {
-                psx_tex_id = 254;
-            } else if let Some(tex_path) = &material.diffuse_texture {
-                // If the texture path is already in here, reuse the corresponding material index
-                if let Some(already_added_id) =
-                    psx_id_tex_mapping.iter().position(|x| *x == *tex_path)
-                {
-                    psx_tex_id = already_added_id;
+    let mut psx_id_tex_mapping: Vec<String> = vec![];
+    let mut psx_id_bpp_mapping: Vec<Option<i32>> = vec![];
+    for material in materials {
+        // First let's figure out what we have in this material. Is it textured? Is it untextured? Is it an occluder?
+        let psx_tex_id;
+
+        if material.name.contains("occlude") {
+            psx_tex_id = 254;
+        } else if let Some(tex_path) = &material.diffuse_texture {
+            // If the texture path is already in here, reuse the corresponding material index
+            let texture_bpp = texture_bpp_override(tex_path, material)?;
+            if let Some(already_added_id) =
+                psx_id_tex_mapping.iter().position(|x| texture_name(x) == texture_name(tex_path))
+            {
+                psx_tex_id = already_added_id;
+                if texture_bpp.is_some() && texture_bpp != psx_id_bpp_mapping[already_added_id] {
+                    warn!("material {} uses {tex_path} with a different bit depth than an earlier material, ignoring it", material.name);
                 }
-                // Otherwise add it to the list
-                else {
-                    psx_tex_id = psx_id_tex_mapping.len();
-                    psx_id_tex_mapping.push(tex_path.to_string());
-                }
-            } else {
-                psx_tex_id = 255;
             }
-
-            // Now we know what this is, let's add it to the mapping
-            material_mapping.push(psx_tex_id);
+            // Otherwise add it to the list
+            else {
+                psx_tex_id = psx_id_tex_mapping.len();
+                psx_id_tex_mapping.push(tex_path.to_string());
+                psx_id_bpp_mapping.push(texture_bpp);
+            }
+        } else {
+            psx_tex_id = 255;
         }
+
+        // Now we know what this is, let's add it to the mapping
+        material_mapping.push(psx_tex_id);
+    }
+
+    if psx_id_tex_mapping.len() > MAX_TEXTURES {
+        return Err(Error::TooManyTextures {
+            count: psx_id_tex_mapping.len(),
+            max: MAX_TEXTURES,
+        });
     }
 
     // Load textures
@@ -67,7 +103,11 @@ pub fn obj2msh_txc(
     let mut txc_psx = TextureCollectionPSX::new();
     let mut mesh_map: HashMap<String, MeshGridEntry> = HashMap::new();
 
-    for tex_path in &psx_id_tex_mapping {
+    for (tex_path, texture_bpp) in psx_id_tex_mapping.iter().zip(&psx_id_bpp_mapping) {
+        let texture_bpp = texture_bpp.unwrap_or(match using_texture_page {
+            false => 4,
+            true => 8,
+        });
         let mut tex_data_src = vec![0xFF; 64 * 64 * 4];
         let mut depth = 4;
         let mut width = 64;
@@ -76,13 +116,17 @@ pub fn obj2msh_txc(
 
         {
             // Load the image file corresponding to the material
-            let input_path = Path::new(&input_obj);
-            let parent_directory = input_path.parent().expect("Invalid file path");
-            let combined_path = parent_directory.join(tex_path);
-            name = String::from(combined_path.to_str().unwrap());
+            let combined_path = texture_dir.join(tex_path);
+            name = combined_path.to_string_lossy().to_string();
             let raw_image = match stb_image::image::load(&name) {
                 stb_image::image::LoadResult::ImageU8(image) => Some(image),
-                _ => None,
+                stb_image::image::LoadResult::ImageF32(_) => {
+                    return Err(Error::UnsupportedPixelFormat { path: name, format: "floating point".to_string() })
+                }
+                stb_image::image::LoadResult::Error(_) => {
+                    warn!("could not load texture {name}, using a blank texture instead");
+                    None
+                }
             };
 
             if let Some(raw_image) = raw_image {
@@ -92,6 +136,10 @@ pub fn obj2msh_txc(
                 height = raw_image.height;
             }
         }
+        check_pixel_format(&name, depth)?;
+        if width > 256 || height > 256 {
+            return Err(Error::ImageTooLarge { path: name, width, height });
+        }
 
         // Create texture cell object
         let mut tex_cell = TextureCellPSX {
@@ -99,11 +147,7 @@ pub fn obj2msh_txc(
             palette: Vec::new(),
             texture_width: width as u8,
             texture_height: height as u8,
-            // todo: unhardcode this - maybe the image file name can end in _4bpp, _8bpp or _15bpp to override it?
-            texture_bpp: match using_texture_page {
-                false => 4,
-                true => 8,
-            },
+            texture_bpp,
             avg_color: 0,
         };
         // Calculate average color
@@ -115,11 +159,6 @@ pub fn obj2msh_txc(
             avg_r += pixel[0] as u32;
             avg_g += pixel[1] as u32;
             avg_b += pixel[2] as u32;
-            match depth {
-                4 => avg_a += pixel[3] as u32,
-                3 => avg_a += 255,
-                _ => panic!(),
-            }
             if depth == 4 {
                 avg_a += pixel[3] as u32;
             } else {
@@ -133,24 +172,34 @@ pub fn obj2msh_txc(
         avg_a /= n_pixels;
         tex_cell.avg_color = avg_r | avg_b << 8 | avg_g << 16 | avg_a << 24;
 
-        // Quantize it to 16 colours
+        // 15-bit direct color textures don't need a palette, so we can store the pixels as they are
+        if texture_bpp == 15 {
+            for pixel in tex_data_src.chunks(depth) {
+                let color16 = match depth == 4 && pixel[3] == 0 {
+                    true => 0,
+                    false => {
+                        1 << 15
+                            | (pixel[2] as u16 >> 3) << 10
+                            | (pixel[1] as u16 >> 3) << 5
+                            | (pixel[0] as u16 >> 3) << 0
+                    }
+                };
+                tex_cell.texture_data.extend(color16.to_le_bytes());
+            }
+            txc_psx.texture_cells.push(tex_cell);
+            txc_psx.texture_names.push(texture_name(tex_path));
+            continue;
+        }
+
+        // Quantize it to 16 or 256 colours
         let mut tex_data_exoquant = Vec::new();
         let mut has_transparent_pixels = false;
         for pixel in tex_data_src.chunks(depth) {
-            match depth {
-                4 => {
-                    if pixel[3] == 0 {
-                        has_transparent_pixels = true;
-                        tex_data_exoquant.push(exoquant::Color::new(0, 0, 0, 0))
-                    } else {
-                        tex_data_exoquant
-                            .push(exoquant::Color::new(pixel[0], pixel[1], pixel[2], 255))
-                    }
-                }
-                3 => {
-                    tex_data_exoquant.push(exoquant::Color::new(pixel[0], pixel[1], pixel[2], 255))
-                }
-                _ => panic!(),
+            if depth == 4 && pixel[3] == 0 {
+                has_transparent_pixels = true;
+                tex_data_exoquant.push(exoquant::Color::new(0, 0, 0, 0))
+            } else {
+                tex_data_exoquant.push(exoquant::Color::new(pixel[0], pixel[1], pixel[2], 255))
             }
         }
 
@@ -172,10 +221,7 @@ pub fn obj2msh_txc(
             histogram,
             &SimpleColorSpace::default(),
             &optimizer::WeightedKMeans,
-            match using_texture_page {
-                false => 16,
-                true => 256,
-            },
+            1 << texture_bpp,
         );
         let mut palette = optimizer::WeightedKMeans.optimize_palette(
             &SimpleColorSpace::default(),
@@ -207,9 +253,7 @@ pub fn obj2msh_txc(
 
         if n_transparent_colors > 0 {
             // Swap the transparent and first colors
-            let temp = palette[0];
-            palette[0] = palette[transparent_index];
-            palette[transparent_index] = temp;
+            palette.swap(0, transparent_index);
 
             // Then remap the indices in the texture
             for pixel in &mut indexed_data {
@@ -254,36 +298,22 @@ pub fn obj2msh_txc(
             }
         }
 
-        // Convert indices to 4 bit
-        if using_texture_page {
+        // Pack the indices, 8 bit textures store one pixel per byte, 4 bit textures store two
+        if texture_bpp == 8 {
             for i in 0..(width * height) {
-                if i < indexed_data.len() {
-                    tex_cell.texture_data.push(indexed_data[i]);
-                } else {
-                    tex_cell.texture_data.push(0);
-                    tex_cell.texture_data.push(0);
-                    tex_cell.texture_data.push(0);
-                    tex_cell.texture_data.push(0);
-                }
+                tex_cell.texture_data.push(indexed_data.get(i).copied().unwrap_or(0));
             }
         } else {
             for i in (0..(width * height)).step_by(2) {
-                if (i + 1) < indexed_data.len() {
-                    tex_cell
-                        .texture_data
-                        .push((indexed_data[i + 1] << 4) | (indexed_data[i + 0]));
-                } else {
-                    tex_cell.texture_data.push(0);
-                    tex_cell.texture_data.push(0);
-                    tex_cell.texture_data.push(0);
-                    tex_cell.texture_data.push(0);
-                }
+                let pixel0 = indexed_data.get(i + 0).copied().unwrap_or(0);
+                let pixel1 = indexed_data.get(i + 1).copied().unwrap_or(0);
+                tex_cell.texture_data.push((pixel1 << 4) | pixel0);
             }
         }
 
         // Add this cell to the collection
         txc_psx.texture_cells.push(tex_cell);
-        txc_psx.texture_names.push(name);
+        txc_psx.texture_names.push(texture_name(tex_path));
     }
 
     // debug
@@ -297,7 +327,7 @@ pub fn obj2msh_txc(
     }
 
     // Loop over every mesh in the model. We want to combine them all.
-    for model in &models {
+    for model in models {
         let mut curr_index = 0;
         let mut triangles;
         let mut quads;
@@ -311,8 +341,7 @@ pub fn obj2msh_txc(
 
         let face_arities = match model.mesh.face_arities.is_empty() {
             false => model.mesh.face_arities.clone(),
-            true => std::iter::repeat(3)
-                .take(model.mesh.indices.len() / 3)
+            true => std::iter::repeat_n(3, model.mesh.indices.len() / 3)
                 .collect(),
         };
 
@@ -320,11 +349,16 @@ pub fn obj2msh_txc(
             let mut curr_primitive = Vec::<VertexPSX>::new();
             for in_face_index in curr_index as usize..(curr_index + arity) as usize {
                 let index = model.mesh.indices[in_face_index] as usize;
-                let (h, mut s, l) = rgb_to_hsl((
-                    model.mesh.vertex_color[index * 3 + 0],
-                    model.mesh.vertex_color[index * 3 + 1],
-                    model.mesh.vertex_color[index * 3 + 2],
-                ));
+                // Meshes without vertex colors are drawn white
+                let vertex_color = match model.mesh.vertex_color.is_empty() {
+                    false => (
+                        model.mesh.vertex_color[index * 3 + 0],
+                        model.mesh.vertex_color[index * 3 + 1],
+                        model.mesh.vertex_color[index * 3 + 2],
+                    ),
+                    true => (1.0, 1.0, 1.0),
+                };
+                let (h, mut s, l) = rgb_to_hsl(vertex_color);
                 //l = l.powf(1.0/1.0);
                 s *= 1.25;
                 let (r, g, b) = hsl_to_rgb((h, s, l));
@@ -332,35 +366,32 @@ pub fn obj2msh_txc(
                 let (texture_id, texture_width, texture_height) = match model.mesh.material_id {
                     None => (255_usize, 255.0_f32, 255.0_f32),
                     Some(id) => {
-                        if material_mapping[id as usize] >= 128 {
-                            (material_mapping[id as usize], 255.0, 255.0)
+                        if material_mapping[id] >= 128 {
+                            (material_mapping[id], 255.0, 255.0)
                         }
                         else {
-                            let cell = &txc_psx.texture_cells[material_mapping[id as usize]];
-                            (material_mapping[id as usize], cell.texture_width as f32, cell.texture_height as f32)
+                            let cell = &txc_psx.texture_cells[material_mapping[id]];
+                            (material_mapping[id], cell.texture_width as f32, cell.texture_height as f32)
                         }
                     },
                 };
 
+                let [pos_x, pos_y, pos_z] = position_to_psx(&model.mesh.positions, index, &model.name)?;
+                let texcoord = |i: usize| model.mesh.texcoords.get(index * 2 + i).copied().unwrap_or(0.0);
+                let normal = |i: usize| model.mesh.normals.get(index * 3 + i).copied().unwrap_or(0.0);
                 let vert = VertexPSX {
-                    pos_x: (model.mesh.positions[index * 3 + 0] * -1024.0).clamp(-32768.0, 32767.0)
-                        as i16,
-                    pos_y: (model.mesh.positions[index * 3 + 1] * -1024.0).clamp(-32768.0, 32767.0)
-                        as i16,
-                    pos_z: (model.mesh.positions[index * 3 + 2] * 1024.0).clamp(-32768.0, 32767.0)
-                        as i16,
+                    pos_x,
+                    pos_y,
+                    pos_z,
                     color_r: (r * 255.0).clamp(0.0, 255.0) as u8,
                     color_g: (g * 255.0).clamp(0.0, 255.0) as u8,
                     color_b: (b * 255.0).clamp(0.0, 255.0) as u8,
-                    tex_u: (model.mesh.texcoords[index * 2 + 0] * (texture_width - 1.0)).round() as u8,
-                    tex_v: ((texture_height - 1.0) - (model.mesh.texcoords[index * 2 + 1] * (texture_height - 1.0))).round() as u8,
+                    tex_u: (texcoord(0) * (texture_width - 1.0)).round() as u8,
+                    tex_v: ((texture_height - 1.0) - (texcoord(1) * (texture_height - 1.0))).round() as u8,
                     texture_id: texture_id as u8,
-                    normal_x: (model.mesh.normals[index * 3 + 0] * 127.0).clamp(-127.0, 127.0)
-                        as i8,
-                    normal_y: (model.mesh.normals[index * 3 + 1] * 127.0).clamp(-127.0, 127.0)
-                        as i8,
-                    normal_z: (model.mesh.normals[index * 3 + 2] * 127.0).clamp(-127.0, 127.0)
-                        as i8,
+                    normal_x: (normal(0) * 127.0).clamp(-127.0, 127.0) as i8,
+                    normal_y: (normal(1) * 127.0).clamp(-127.0, 127.0) as i8,
+                    normal_z: (normal(2) * 127.0).clamp(-127.0, 127.0) as i8,
                 };
                 curr_primitive.push(vert);
             }
@@ -426,7 +457,7 @@ pub fn obj2msh_txc(
         mesh_map.insert(model.name.clone(), MeshGridEntry { triangles, quads });
     }
 
-    let mode = match split {
+    let mode = match settings.split {
         true => 4,
         false => 0,
     };
@@ -656,8 +687,36 @@ pub fn obj2msh_txc(
         }
     }
 
-    model_psx.save(Path::new(&output_msh)).unwrap();
-    txc_psx.save(Path::new(&output_txc)).unwrap();
+    Ok((model_psx, txc_psx))
+}
+
+/// The name stored in the texture collection is the path relative to the OBJ file, so textures with the same file name
+/// in different directories stay apart. Separators are always `/`, so the names don't depend on the OS the level was made on
+fn texture_name(tex_path: &str) -> String {
+    tex_path.replace('\\', "/").trim_start_matches("./").to_string()
+}
+
+/// Finds the bit depth a texture asks for. A `psx_bpp` material parameter wins over a `_4bpp`, `_8bpp` or `_15bpp` file name suffix
+fn texture_bpp_override(tex_path: &str, material: &tobj::Material) -> Result<Option<i32>> {
+    if let Some(value) = material.unknown_param.get("psx_bpp") {
+        return match value.trim() {
+            "4" => Ok(Some(4)),
+            "8" => Ok(Some(8)),
+            "15" | "16" => Ok(Some(15)),
+            _ => Err(Error::InvalidMaterial {
+                material: material.name.clone(),
+                reason: format!("psx_bpp should be 4, 8 or 15, not \"{value}\""),
+            }),
+        };
+    }
+
+    let stem = Path::new(tex_path).file_stem().unwrap_or_default().to_string_lossy();
+    for (suffix, bpp) in [("_4bpp", 4), ("_8bpp", 8), ("_15bpp", 15), ("_16bpp", 15)] {
+        if stem.ends_with(suffix) {
+            return Ok(Some(bpp));
+        }
+    }
+    Ok(None)
 }
 
 fn split_equal_based_on_aabb(
@@ -0,0 +1,120 @@
+use std::collections::HashMap;
+
+use log::{debug, info, warn};
+
+use crate::{helpers::psx_to_position, psx_structs::CollVertexPSX};
+
+/// Problems found in a collision mesh while welding it
+#[derive(Debug, Default, PartialEq)]
+pub struct WeldReport {
+    /// Largest distance between an OBJ vertex and its 16-bit position, in OBJ units
+    pub max_quantization_error: f32,
+    /// Number of vertices that were snapped onto a nearby vertex
+    pub n_welded_vertices: usize,
+    /// Largest distance a vertex was moved by welding, in OBJ units
+    pub max_weld_distance: f32,
+    /// Triangles with two or more vertices in the same place. These are removed
+    pub n_degenerate_triangles: usize,
+    /// Triangles with three different vertices on one line, so they have no normal. These are removed too
+    pub n_zero_normal_triangles: usize,
+    /// Edges that only belong to one triangle, as pairs of OBJ positions. Inside a level these are cracks
+    pub open_edges: Vec<([f32; 3], [f32; 3])>,
+}
+
+impl WeldReport {
+    pub fn log(&self) {
+        info!(
+            "collision mesh: max quantization error {:.5}, welded {} vertices (moved up to {:.5})",
+            self.max_quantization_error, self.n_welded_vertices, self.max_weld_distance
+        );
+        if self.n_degenerate_triangles > 0 {
+            warn!("removed {} degenerate triangles from the collision mesh", self.n_degenerate_triangles);
+        }
+        if self.n_zero_normal_triangles > 0 {
+            warn!("removed {} collision triangles with a zero-length normal", self.n_zero_normal_triangles);
+        }
+        if !self.open_edges.is_empty() {
+            warn!(
+                "the collision mesh has {} edges that only belong to one triangle, these are cracks unless they're on the edge of the level",
+                self.open_edges.len()
+            );
+            for (a, b) in &self.open_edges {
+                debug!("open edge from ({}, {}, {}) to ({}, {}, {})", a[0], a[1], a[2], b[0], b[1], b[2]);
+            }
+        }
+    }
+}
+
+fn distance(a: [i16; 3], b: [i16; 3]) -> f32 {
+    glam::Vec3::from(psx_to_position(a)).distance(glam::Vec3::from(psx_to_position(b)))
+}
+
+/// Snaps every vertex onto the first vertex found within `tolerance` (in PS1 units) on every axis,
+/// then removes triangles that collapsed and finds edges that aren't shared
+pub fn weld(triangles: &mut Vec<CollVertexPSX>, tolerance: i32, report: &mut WeldReport) {
+    // Any vertex within the tolerance is at most one grid cell away
+    let cell_size = tolerance.max(1);
+    let cell_of = |position: [i16; 3]| position.map(|value| (value as i32).div_euclid(cell_size));
+    let mut grid = HashMap::<[i32; 3], Vec<[i16; 3]>>::new();
+
+    for vertex in triangles.iter_mut() {
+        let position = [vertex.pos_x, vertex.pos_y, vertex.pos_z];
+        let cell = cell_of(position);
+
+        let mut closest: Option<[i16; 3]> = None;
+        for x in -1..=1 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    let Some(candidates) = grid.get(&[cell[0] + x, cell[1] + y, cell[2] + z]) else {
+                        continue;
+                    };
+                    for &candidate in candidates {
+                        let in_range = (0..3).all(|i| (candidate[i] as i32 - position[i] as i32).abs() <= tolerance);
+                        if in_range && closest.is_none_or(|closest| distance(candidate, position) < distance(closest, position)) {
+                            closest = Some(candidate);
+                        }
+                    }
+                }
+            }
+        }
+
+        match closest {
+            Some(target) if target != position => {
+                report.n_welded_vertices += 1;
+                report.max_weld_distance = report.max_weld_distance.max(distance(target, position));
+                [vertex.pos_x, vertex.pos_y, vertex.pos_z] = target;
+            }
+            Some(_) => {}
+            None => grid.entry(cell).or_default().push(position),
+        }
+    }
+
+    // Remove triangles that don't have an area anymore
+    let mut kept = Vec::with_capacity(triangles.len());
+    for triangle in triangles.chunks_exact(3) {
+        let [v0, v1, v2] = [0, 1, 2].map(|i| glam::I64Vec3::new(triangle[i].pos_x as i64, triangle[i].pos_y as i64, triangle[i].pos_z as i64));
+        if v0 == v1 || v1 == v2 || v2 == v0 {
+            report.n_degenerate_triangles += 1;
+            continue;
+        }
+        if (v1 - v0).cross(v2 - v0) == glam::I64Vec3::ZERO {
+            report.n_zero_normal_triangles += 1;
+            continue;
+        }
+        kept.extend_from_slice(triangle);
+    }
+    *triangles = kept;
+
+    // Count how many triangles use each edge, regardless of its direction
+    let mut edges = HashMap::<([i16; 3], [i16; 3]), usize>::new();
+    for triangle in triangles.chunks_exact(3) {
+        for i in 0..3 {
+            let a = [triangle[i].pos_x, triangle[i].pos_y, triangle[i].pos_z];
+            let b = [triangle[(i + 1) % 3].pos_x, triangle[(i + 1) % 3].pos_y, triangle[(i + 1) % 3].pos_z];
+            *edges.entry((a.min(b), a.max(b))).or_default() += 1;
+        }
+    }
+    let mut open_edges: Vec<_> = edges.into_iter().filter(|(_, count)| *count == 1).map(|(edge, _)| edge).collect();
+    open_edges.sort();
+    report.open_edges = open_edges.into_iter().map(|(a, b)| (psx_to_position(a), psx_to_position(b))).collect();
+}
@@ -0,0 +1,120 @@
+mod common;
+
+use common::software_settings;
+use obj2psx::{
+    collision::{convert_collision, CollisionSettings},
+    obj2msh_txc_col,
+    psx_structs::{CollModelPSX, ModelPSX},
+    split_models, VisualSettings,
+};
+
+fn named(name: &str) -> tobj::Model {
+    tobj::Model::new(tobj::Mesh::default(), name.to_string())
+}
+
+fn names(models: &[tobj::Model]) -> Vec<&str> {
+    models.iter().map(|model| model.name.as_str()).collect()
+}
+
+#[test]
+fn prefixes_pick_the_output() {
+    let models = [
+        named("floor"),
+        named("COL_STAIRS_RAMP"),
+        named("NOCOL_GRASS"),
+        named("COL_BOX_CRATE"),
+        named("TRIGGER_LEVEL_EXIT"),
+        named("NAVLINK_LADDER_01"),
+    ];
+    let (visual, collision) = split_models(&models);
+    assert_eq!(names(&visual), ["floor", "GRASS"]);
+    assert_eq!(names(&collision), ["floor", "STAIRS_RAMP", "BOX_CRATE", "TRIGGER_LEVEL_EXIT", "NAVLINK_LADDER_01"]);
+}
+
+/// A floor polygon in the XZ plane, wound so it faces up
+fn polygon_model(corners: &[[f32; 2]]) -> tobj::Model {
+    let mesh = tobj::Mesh {
+        positions: corners.iter().flat_map(|&[x, z]| [x, 0.0, z]).collect(),
+        indices: (0..corners.len() as u32).collect(),
+        face_arities: vec![corners.len() as u32],
+        ..Default::default()
+    };
+    tobj::Model::new(mesh, "polygon".to_string())
+}
+
+#[test]
+fn quads_become_collision_triangles() {
+    let quad = polygon_model(&[[0.0, 0.0], [0.0, 1.0], [1.0, 1.0], [1.0, 0.0]]);
+
+    // Plain collision conversion only uses triangles, like it always did
+    let col = convert_collision(std::slice::from_ref(&quad), &[], &software_settings()).unwrap();
+    assert!(col.triangles.is_empty());
+
+    let settings = CollisionSettings {
+        triangulate_polygons: true,
+        ..software_settings()
+    };
+    let col = convert_collision(&[quad], &[], &settings).unwrap();
+    assert_eq!(col.triangles.len(), 2);
+    assert!(col.triangles.iter().all(|triangle| triangle.normal.y > 0));
+
+    // A fan from the first corner of this arrow shape would cover the notch, so it's skipped
+    let arrow = polygon_model(&[[0.0, 0.0], [0.5, 0.5], [0.0, 1.0], [1.0, 1.0], [1.0, 0.0]]);
+    let col = convert_collision(&[arrow], &[], &settings).unwrap();
+    assert!(col.triangles.is_empty());
+}
+
+#[test]
+fn one_obj_into_mesh_and_collision() {
+    let dir = std::env::temp_dir().join(format!("obj2psx_combined_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let obj = dir.join("level.obj");
+    std::fs::write(
+        &obj,
+        "\
+o floor
+v 0 0 0
+v 1 0 0
+v 1 0 1
+v 0 0 1
+f 1 4 3 2
+o COL_invisible_wall
+v 0.5 0 0
+v 0.5 1 0
+v 0.5 1 1
+f 5 6 7
+o NOCOL_grass
+v 0.25 0 0.25
+v 0.25 0.1 0.25
+v 0.3 0 0.25
+f 8 9 10
+o MARKER_spawn
+v 0.5 0 0.5
+v 0.5 0 0.5
+v 0.5 0 0.5
+f 11 12 13
+",
+    )
+    .unwrap();
+
+    let output = |extension: &str| dir.join(format!("level.{extension}")).to_string_lossy().to_string();
+    obj2msh_txc_col(
+        obj.to_string_lossy().to_string(),
+        output("msh"),
+        output("txc"),
+        output("col"),
+        &VisualSettings::default(),
+        &software_settings(),
+    )
+    .unwrap();
+    let msh = ModelPSX::load(&dir.join("level.msh")).unwrap();
+    let col = CollModelPSX::load(&dir.join("level.col")).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    // The floor quad and the grass triangle are visible, the floor and the wall are solid
+    let mut mesh_names: Vec<&str> = msh.meshes.iter().map(|mesh| mesh.name.as_str()).collect();
+    mesh_names.sort();
+    assert_eq!(mesh_names, ["floor", "grass"]);
+    assert_eq!(col.triangles.len(), 3);
+    assert_eq!(col.gameplay_objects.len(), 1);
+}
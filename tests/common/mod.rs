@@ -0,0 +1,80 @@
+//! Fixtures shared by the integration tests. Not every test uses all of them
+#![allow(dead_code)]
+
+use obj2psx::{
+    collision::CollisionSettings,
+    psx_structs::{CollModelPSX, VertexPSX},
+};
+
+/// A mesh vertex where every field depends on `i`, so vertices that get mixed up are noticed
+pub fn vertex(i: i16) -> VertexPSX {
+    VertexPSX {
+        pos_x: i * 3,
+        pos_y: -i,
+        pos_z: i * 100,
+        color_r: i as u8,
+        color_g: 128,
+        color_b: 255 - i as u8,
+        tex_u: i as u8 * 2,
+        tex_v: 63 - i as u8,
+        texture_id: 1,
+        normal_x: -127,
+        normal_y: i as i8,
+        normal_z: 127,
+    }
+}
+
+/// Writes a collision model, checks that reading it back gives the same model, and returns the file
+pub fn round_trip(col: &CollModelPSX) -> Vec<u8> {
+    let mut data = Vec::new();
+    col.write(&mut data).unwrap();
+    assert_eq!(CollModelPSX::read(&data).unwrap(), *col);
+    data
+}
+
+/// Collision settings that work on machines without a GPU
+pub fn software_settings() -> CollisionSettings {
+    CollisionSettings {
+        software_renderer: true,
+        ..Default::default()
+    }
+}
+
+/// A floor quad of 1 by 1, and a triangle going up from one of its edges that faces down at about 63 degrees from vertical
+pub fn floor_model() -> tobj::Model {
+    let mesh = tobj::Mesh {
+        positions: vec![
+            0.0, 0.0, 0.0, //
+            1.0, 0.0, 0.0, //
+            1.0, 0.0, 1.0, //
+            0.0, 0.0, 1.0, //
+            0.5, 1.0, 0.5, //
+        ],
+        indices: vec![0, 2, 1, 0, 3, 2, 0, 1, 4],
+        ..Default::default()
+    };
+    tobj::Model::new(mesh, "floor".to_string())
+}
+
+/// A flat floor made of `size` by `size` quads, each split into two triangles
+pub fn grid_model(size: usize, spacing: f32) -> tobj::Model {
+    platform_model(0.0, 0.0, size, spacing)
+}
+
+/// Same as `grid_model`, but starting at `x` and at height `y`
+pub fn platform_model(x0: f32, y: f32, size: usize, spacing: f32) -> tobj::Model {
+    let mut mesh = tobj::Mesh::default();
+    for z in 0..=size {
+        for x in 0..=size {
+            mesh.positions.extend_from_slice(&[x0 + x as f32 * spacing, y, z as f32 * spacing]);
+        }
+    }
+    let index = |x: usize, z: usize| (z * (size + 1) + x) as u32;
+    for z in 0..size {
+        for x in 0..size {
+            mesh.indices.extend_from_slice(&[index(x, z), index(x + 1, z + 1), index(x + 1, z)]);
+            mesh.indices.extend_from_slice(&[index(x, z), index(x, z + 1), index(x + 1, z + 1)]);
+        }
+    }
+    tobj::Model::new(mesh, "grid".to_string())
+}
@@ -0,0 +1,65 @@
+use obj2psx::{
+    collision::{convert_collision, CollisionSettings},
+    debug_obj::write_debug_obj,
+};
+
+/// A sloped floor and a wall, with the triangles in OBJ winding order
+fn ramp_model() -> tobj::Model {
+    let mesh = tobj::Mesh {
+        positions: vec![
+            0.0, 0.0, 0.0, //
+            1.0, 0.25, 0.0, //
+            1.0, 0.25, 1.0, //
+            0.0, 0.0, 1.0, //
+            0.0, 1.0, 0.0, //
+        ],
+        indices: vec![0, 2, 1, 0, 3, 2, 0, 1, 4],
+        ..Default::default()
+    };
+    tobj::Model::new(mesh, "ramp".to_string())
+}
+
+fn normal(positions: &[f32], indices: &[u32]) -> glam::Vec3 {
+    let [a, b, c] = [0, 1, 2].map(|i| glam::Vec3::from_slice(&positions[indices[i] as usize * 3..]));
+    (b - a).cross(c - a).normalize()
+}
+
+#[test]
+fn debug_obj_matches_the_input() {
+    let settings = CollisionSettings {
+        software_renderer: true,
+        ..Default::default()
+    };
+    let input = ramp_model();
+    let col = convert_collision(std::slice::from_ref(&input), &[], &settings).unwrap();
+
+    let path = std::env::temp_dir().join(format!("obj2psx_debug_{}.obj", std::process::id()));
+    write_debug_obj(&col, &path).unwrap();
+    let (models, materials) = tobj::load_obj(&path, &tobj::LoadOptions::default()).unwrap();
+    let materials = materials.unwrap();
+    std::fs::remove_file(&path).unwrap();
+    std::fs::remove_file(path.with_extension("mtl")).unwrap();
+
+    let names: Vec<&str> = models.iter().map(|model| model.name.as_str()).collect();
+    assert!(names.contains(&"collision_terrain"));
+    assert!(names.contains(&"bvh_depth_0"));
+    assert!(names.contains(&"nav_graph_0_walk"));
+    assert!(materials.iter().any(|material| material.name == "floor"));
+
+    // Every triangle comes back in the same place, facing the same way
+    let surface: Vec<&tobj::Model> = models.iter().filter(|model| model.name == "collision_surface").collect();
+    let n_triangles: usize = surface.iter().map(|model| model.mesh.indices.len() / 3).sum();
+    assert_eq!(n_triangles, 3);
+    for model in surface {
+        for triangle in model.mesh.indices.chunks(3) {
+            let found = input.mesh.indices.chunks(3).any(|original| {
+                let same_corners = (0..3).all(|i| {
+                    let a = glam::Vec3::from_slice(&model.mesh.positions[triangle[i] as usize * 3..]);
+                    (0..3).any(|j| a.distance(glam::Vec3::from_slice(&input.mesh.positions[original[j] as usize * 3..])) < 1e-3)
+                });
+                same_corners && normal(&model.mesh.positions, triangle).dot(normal(&input.mesh.positions, original)) > 0.99
+            });
+            assert!(found, "triangle {triangle:?} doesn't match the input");
+        }
+    }
+}
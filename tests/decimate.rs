@@ -0,0 +1,86 @@
+mod common;
+
+use common::{grid_model, software_settings};
+use obj2psx::{
+    collision::{convert_collision, CollisionSettings},
+    decimate::decimate,
+    psx_structs::CollVertexPSX,
+};
+
+/// A grid of `size` by `size` quads of 256 units, with the terrain ID and height of every grid vertex given by closures
+fn grid(size: i16, terrain_id: impl Fn(i16) -> u16, height: impl Fn(i16, i16) -> i16) -> Vec<CollVertexPSX> {
+    let vertex = |x: i16, z: i16, terrain_id: u16| CollVertexPSX {
+        pos_x: x * 256,
+        pos_y: height(x, z),
+        pos_z: z * 256,
+        terrain_id,
+    };
+    let mut triangles = vec![];
+    for z in 0..size {
+        for x in 0..size {
+            let id = terrain_id(x);
+            triangles.extend([vertex(x, z, id), vertex(x + 1, z + 1, id), vertex(x + 1, z, id)]);
+            triangles.extend([vertex(x, z, id), vertex(x, z + 1, id), vertex(x + 1, z + 1, id)]);
+        }
+    }
+    triangles
+}
+
+/// Twice the area of the triangles with this terrain ID, projected onto the floor
+fn area(triangles: &[CollVertexPSX], terrain_id: u16) -> i64 {
+    triangles
+        .chunks_exact(3)
+        .filter(|triangle| triangle[0].terrain_id == terrain_id)
+        .map(|triangle| {
+            let [a, b, c] = [0, 1, 2].map(|i| glam::I64Vec2::new(triangle[i].pos_x as i64, triangle[i].pos_z as i64));
+            (b - a).perp_dot(c - a).abs()
+        })
+        .sum()
+}
+
+#[test]
+fn merges_flat_regions() {
+    let mut triangles = grid(8, |_| 0, |_, _| 0);
+    let report = decimate(&mut triangles, 400.0);
+    assert_eq!((report.n_triangles_before, report.n_triangles_after), (128, 2));
+    assert_eq!(area(&triangles, 0), 2 * 2048 * 2048);
+}
+
+#[test]
+fn keeps_terrain_borders() {
+    let mut triangles = grid(8, |x| if x < 3 { 1 } else { 2 }, |_, _| 0);
+    let report = decimate(&mut triangles, 400.0);
+    assert!(report.n_triangles_after <= 8);
+    assert_eq!(area(&triangles, 1), 2 * 768 * 2048);
+    assert_eq!(area(&triangles, 2), 2 * 1280 * 2048);
+}
+
+#[test]
+fn stays_within_the_error() {
+    // A bump of 40 units in the middle of the grid
+    let bumpy = || grid(8, |_| 0, |x, z| if (x, z) == (4, 4) { 40 } else { 0 });
+    let is_bump = |vertex: &CollVertexPSX| vertex.pos_y == 40;
+
+    let mut triangles = bumpy();
+    let report = decimate(&mut triangles, 10.0);
+    assert!(report.n_triangles_after < report.n_triangles_before);
+    assert!(triangles.iter().any(is_bump));
+    assert!(report.max_error * 1024.0 <= 10.0 + 1e-3);
+
+    // A big enough error flattens the bump too
+    let mut triangles = bumpy();
+    decimate(&mut triangles, 512.0);
+    assert!(!triangles.iter().any(is_bump));
+    assert_eq!(triangles.len(), 2 * 3);
+}
+
+#[test]
+fn decimates_before_building_the_bvh() {
+    let settings = CollisionSettings {
+        decimate: Some(0.0),
+        ..software_settings()
+    };
+    let col = convert_collision(&[grid_model(4, 0.25)], &[], &settings).unwrap();
+    assert_eq!(col.triangles.len(), 2);
+    assert!(col.triangles.iter().all(|triangle| triangle.normal.y > 0));
+}
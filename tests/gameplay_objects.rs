@@ -0,0 +1,62 @@
+mod common;
+
+use common::{grid_model, round_trip, software_settings};
+use obj2psx::{
+    collision::{convert_collision, GameplayObjectKind},
+    inspect::inspect,
+    psx_structs::{CollHeader, CollModelPSX},
+};
+
+/// A single triangle spanning from `min` to `max`
+fn object_model(name: &str, min: [f32; 3], max: [f32; 3]) -> tobj::Model {
+    let mesh = tobj::Mesh {
+        positions: vec![
+            min[0], min[1], min[2], //
+            max[0], min[1], max[2], //
+            min[0], max[1], max[2], //
+        ],
+        indices: vec![0, 1, 2],
+        ..Default::default()
+    };
+    tobj::Model::new(mesh, name.to_string())
+}
+
+fn models() -> Vec<tobj::Model> {
+    vec![
+        grid_model(1, 4.0),
+        object_model("TRIGGER_LEVEL_EXIT", [3.0, 0.0, 3.0], [4.0, 2.0, 4.0]),
+        object_model("MARKER_SPAWN", [0.5, 0.0, 0.5], [0.5, 0.0, 0.5]),
+    ]
+}
+
+#[test]
+fn triggers_and_markers_are_not_solid() {
+    let col = convert_collision(&models(), &[], &software_settings()).unwrap();
+    assert_eq!(col.triangles.len(), 2);
+    assert_eq!(col.n_primitives(), 2);
+
+    let [exit, spawn] = [&col.gameplay_objects[0], &col.gameplay_objects[1]];
+    assert_eq!((exit.kind, exit.name.as_str()), (GameplayObjectKind::Trigger, "TRIGGER_LEVEL_EXIT"));
+    assert_eq!((spawn.kind, spawn.name.as_str()), (GameplayObjectKind::Marker, "MARKER_SPAWN"));
+
+    // Bounds are in the same space as the triangles, where X and Y are 512 * 1024 per OBJ unit and Z is flipped
+    let scale = 512 * 1024;
+    assert_eq!(exit.bounds.min, glam::IVec3::new(3, 0, -4) * scale);
+    assert_eq!(exit.bounds.max, glam::IVec3::new(4, 2, -3) * scale);
+    assert_eq!(spawn.bounds.min, spawn.bounds.max);
+
+    let empty = tobj::Model::new(tobj::Mesh::default(), "MARKER_NOWHERE".to_string());
+    assert!(convert_collision(&[grid_model(1, 4.0), empty], &[], &software_settings()).is_err());
+}
+
+#[test]
+fn gameplay_objects_round_trip() {
+    let col = convert_collision(&models(), &[], &software_settings()).unwrap();
+    let mut data = round_trip(&col);
+    assert!(inspect(&data).unwrap().to_json().contains("\"name\":\"TRIGGER_LEVEL_EXIT\""));
+
+    // Unknown kinds are rejected
+    let header = CollHeader::read(&data).unwrap();
+    data[header.gameplay_objects_offset] = 7;
+    assert!(CollModelPSX::read(&data).is_err());
+}
@@ -0,0 +1,114 @@
+mod common;
+
+use common::{floor_model, software_settings, vertex};
+use obj2psx::{
+    collision::convert_collision,
+    inspect::{inspect, inspect_file, Value},
+    psx_structs::{MeshPSX, ModelPSX, TextureCellPSX, TextureCollectionPSX, FCOL_VERSION},
+};
+
+/// Checks that brackets and quotes are balanced and that no number is one JSON doesn't have, which catches most broken output
+fn assert_json_shape(json: &str) {
+    let mut depth = Vec::new();
+    let mut in_string = false;
+    let mut escaped = false;
+    for c in json.chars() {
+        match (in_string, c) {
+            (true, _) if escaped => escaped = false,
+            (true, '\\') => escaped = true,
+            (true, '"') => in_string = false,
+            (true, _) => {}
+            (false, '"') => in_string = true,
+            (false, '{' | '[') => depth.push(c),
+            (false, '}') => assert_eq!(depth.pop(), Some('{'), "{json}"),
+            (false, ']') => assert_eq!(depth.pop(), Some('['), "{json}"),
+            (false, _) => assert!(!c.is_alphabetic() || "truefalsn".contains(c), "{json}"),
+        }
+    }
+    assert!(depth.is_empty() && !in_string, "{json}");
+}
+
+fn inspect_both(data: &[u8]) -> (String, String) {
+    let value = inspect(data).unwrap();
+    let json = value.to_json();
+    assert_json_shape(&json);
+    (value.to_text(), json)
+}
+
+#[test]
+fn inspects_msh() {
+    let model = ModelPSX {
+        meshes: vec![MeshPSX {
+            verts: (0..3).map(vertex).collect(),
+            n_triangles: 1,
+            n_quads: 0,
+            name: "triangle".to_string(),
+        }],
+    };
+    let mut data = Vec::new();
+    model.write(&mut data).unwrap();
+
+    let (text, json) = inspect_both(&data);
+    assert!(text.starts_with("format: \"FMSH\"\nheader:\n  n_submeshes: 1\n"), "{text}");
+    assert!(text.contains("n_vertices: 3\n"), "{text}");
+    assert!(text.contains("  [0]\n    name: \"triangle\"\n"), "{text}");
+    assert!(text.contains("    aabb_min: [0, -2, 0]\n"), "{text}");
+    assert!(json.starts_with("{\"format\":\"FMSH\",\"header\":{\"n_submeshes\":1,"), "{json}");
+    assert!(json.contains("\"submeshes\":[{\"name\":\"triangle\",\"vertex_start\":0,\"n_triangles\":1,\"n_quads\":0,"), "{json}");
+}
+
+#[test]
+fn inspects_txc() {
+    let txc = TextureCollectionPSX {
+        texture_cells: vec![TextureCellPSX {
+            texture_data: (0..32).collect(),
+            palette: (0..16).collect(),
+            texture_width: 8,
+            texture_height: 8,
+            avg_color: 0x80FF8040,
+            texture_bpp: 4,
+        }],
+        texture_names: vec!["walls/\"brick\".png".to_string()],
+    };
+    let mut data = Vec::new();
+    txc.write(&mut data).unwrap();
+
+    let (text, json) = inspect_both(&data);
+    assert!(text.contains("    name: \"walls/\\\"brick\\\".png\"\n"), "{text}");
+    assert!(text.contains("    size: [8, 8]\n    bpp: 4\n"), "{text}");
+    assert!(text.contains("    avg_color: \"80FF8040\"\n"), "{text}");
+    assert!(json.contains("\"name\":\"walls/\\\"brick\\\".png\""), "{json}");
+    assert!(json.contains("\"size\":[8,8],\"bpp\":4"), "{json}");
+}
+
+#[test]
+fn inspects_col() {
+    let col = convert_collision(&[floor_model()], &[], &software_settings()).unwrap();
+    let dir = std::env::temp_dir().join(format!("obj2psx_inspect_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("floor.col");
+    col.save(&path).unwrap();
+    let text = inspect_file(&path, false).unwrap();
+    let json = inspect_file(&path, true).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert!(text.starts_with(&format!("format: \"FCOL\"\nheader:\n  version: {FCOL_VERSION}\n")), "{text}");
+    assert!(text.contains("\nwide_indices: false\nprecomputed_planes: false\nn_triangles: 3\n"), "{text}");
+    assert!(text.contains("\nbvh:\n  n_nodes: "), "{text}");
+
+    // The BVH statistics are floats, which JSON prints without a fixed precision
+    assert!(json.ends_with("}\n"));
+    assert_json_shape(json.trim_end());
+    assert!(json.contains(",\"wide_indices\":false,\"precomputed_planes\":false,\"n_triangles\":3,"), "{json}");
+    assert!(json.contains("\"avg_leaf_size\":1.5,"), "{json}");
+}
+
+#[test]
+fn json_has_no_infinity_or_nan() {
+    let value = Value::Object(vec![
+        ("nan", f32::NAN.into()),
+        ("infinity", Value::List(vec![f32::INFINITY.into(), f32::NEG_INFINITY.into()])),
+        ("half", 0.5.into()),
+    ]);
+    assert_eq!(value.to_json(), "{\"nan\":null,\"infinity\":[null,null],\"half\":0.5}");
+}
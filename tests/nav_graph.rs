@@ -0,0 +1,230 @@
+mod common;
+
+use common::{grid_model, platform_model, round_trip, software_settings};
+use obj2psx::{
+    collision::{convert_collision, CollisionSettings, NavAgent, NavLinkType},
+    psx_structs::NavGraph,
+};
+
+/// A wall across the whole grid at `x`, with triangles facing both ways so it blocks the line of sight from both sides
+fn wall_model(x: f32, height: f32, size: f32) -> tobj::Model {
+    let mesh = tobj::Mesh {
+        positions: vec![
+            x, 0.0, 0.0, //
+            x, height, 0.0, //
+            x, height, size, //
+            x, 0.0, size, //
+        ],
+        indices: vec![0, 1, 2, 0, 2, 3, 0, 2, 1, 0, 3, 2],
+        ..Default::default()
+    };
+    tobj::Model::new(mesh, "wall".to_string())
+}
+
+/// A nav link helper object, drawn as a single line
+fn link_model(name: &str, from: [f32; 3], to: [f32; 3]) -> tobj::Model {
+    let mesh = tobj::Mesh {
+        positions: from.into_iter().chain(to).collect(),
+        indices: vec![0, 1],
+        face_arities: vec![2],
+        ..Default::default()
+    };
+    tobj::Model::new(mesh, name.to_string())
+}
+
+/// The links from nodes on one side of `x` to nodes on the other side, with their types
+fn links_across(graph: &NavGraph, x: f32) -> Vec<(bool, NavLinkType)> {
+    // The OBJ X axis is flipped in PS1 space, but not in nav graph units, where 1.0 is 128
+    let side = |i: usize| graph.nodes[i].pos_x.abs() as f32 > x * 128.0;
+    let mut links = vec![];
+    for (i, node) in graph.nodes.iter().enumerate() {
+        for (&neighbor, &link_type) in node.neighbors.iter().zip(&node.link_types) {
+            if side(i) != side(neighbor as usize) {
+                links.push((side(i), link_type));
+            }
+        }
+    }
+    links
+}
+
+fn node_distance(graph: &NavGraph, a: usize, b: usize) -> f32 {
+    let position = |i: usize| glam::vec3(graph.nodes[i].pos_x as f32, graph.nodes[i].pos_y as f32, graph.nodes[i].pos_z as f32);
+    position(a).distance(position(b))
+}
+
+#[test]
+fn threads_find_the_same_neighbors() {
+    let model = grid_model(12, 0.1);
+    let threads = |threads: usize| CollisionSettings {
+        threads,
+        ..software_settings()
+    };
+    let single = convert_collision(std::slice::from_ref(&model), &[], &threads(1)).unwrap();
+    let multi = convert_collision(&[model], &[], &threads(4)).unwrap();
+    assert!(single.nav_graphs[0].nodes.iter().any(|node| !node.neighbors.is_empty()));
+    assert_eq!(single.nav_graphs, multi.nav_graphs);
+}
+
+#[test]
+fn one_graph_per_agent() {
+    let small: NavAgent = "neighbors=2,distance=0.5".parse().unwrap();
+    let big: NavAgent = "height=0.5,radius=0.1,neighbors=8".parse().unwrap();
+    let settings = CollisionSettings {
+        nav_agents: vec![small, big],
+        ..software_settings()
+    };
+    let col = convert_collision(&[grid_model(6, 0.1)], &[], &settings).unwrap();
+    assert_eq!(col.nav_graphs.len(), 2);
+
+    let [small, big] = [&col.nav_graphs[0], &col.nav_graphs[1]];
+    assert_eq!((small.max_neighbors, small.max_link_distance), (2, 512));
+    assert_eq!((big.agent_height, big.agent_radius, big.max_neighbors), (512, 102, 8));
+    assert!(small.nodes.iter().all(|node| node.neighbors.len() <= 2));
+    assert!(big.nodes.iter().any(|node| node.neighbors.len() > 4));
+
+    round_trip(&col);
+}
+
+#[test]
+fn links_the_closest_nodes() {
+    let agent: NavAgent = "neighbors=3,distance=0.3".parse().unwrap();
+    let settings = CollisionSettings {
+        nav_agents: vec![agent],
+        ..software_settings()
+    };
+    let col = convert_collision(&[grid_model(5, 0.1)], &[], &settings).unwrap();
+    let graph = &col.nav_graphs[0];
+    let max_distance = graph.max_link_distance as f32 / 8.0;
+
+    // Nothing blocks the line of sight on a flat floor, so the neighbors are just the closest nodes, ties broken by index
+    for (i, node) in graph.nodes.iter().enumerate() {
+        let mut expected: Vec<u32> = (0..graph.nodes.len() as u32)
+            .filter(|&j| j as usize != i && node_distance(graph, i, j as usize) < max_distance)
+            .collect();
+        expected.sort_by(|&a, &b| node_distance(graph, i, a as usize).total_cmp(&node_distance(graph, i, b as usize)).then(a.cmp(&b)));
+        expected.truncate(3);
+        assert_eq!(node.neighbors, expected, "node {i}");
+    }
+}
+
+#[test]
+fn walls_block_links() {
+    let col = convert_collision(&[grid_model(4, 0.25), wall_model(0.5, 1.0, 1.0)], &[], &software_settings()).unwrap();
+    let graph = &col.nav_graphs[0];
+    assert!(graph.nodes.iter().any(|node| !node.neighbors.is_empty()));
+
+    // The OBJ X axis is flipped in PS1 space, but the wall is still at 0.5 * 128 in nav graph units
+    let side = |i: usize| graph.nodes[i].pos_x.abs() > 64;
+    for (i, node) in graph.nodes.iter().enumerate() {
+        for &neighbor in &node.neighbors {
+            assert_eq!(side(i), side(neighbor as usize), "node {i} links through the wall to node {neighbor}");
+        }
+    }
+}
+
+#[test]
+fn bidirectional_links() {
+    let one_way: NavAgent = "neighbors=2".parse().unwrap();
+    let two_way: NavAgent = "neighbors=2,bidirectional=true".parse().unwrap();
+    let settings = CollisionSettings {
+        nav_agents: vec![one_way, two_way],
+        ..software_settings()
+    };
+    let col = convert_collision(&[grid_model(4, 0.1)], &[], &settings).unwrap();
+    let is_symmetric = |graph: &NavGraph| {
+        graph.nodes.iter().enumerate().all(|(i, node)| {
+            node.neighbors.iter().all(|&neighbor| graph.nodes[neighbor as usize].neighbors.contains(&(i as u32)))
+        })
+    };
+    assert!(!is_symmetric(&col.nav_graphs[0]));
+    assert!(is_symmetric(&col.nav_graphs[1]));
+
+    // Every one-way link is still there
+    for (one_way, two_way) in col.nav_graphs[0].nodes.iter().zip(&col.nav_graphs[1].nodes) {
+        assert!(one_way.neighbors.iter().all(|neighbor| two_way.neighbors.contains(neighbor)));
+    }
+}
+
+#[test]
+fn rejects_bad_agents() {
+    assert!("neighbors=0".parse::<NavAgent>().is_err());
+    assert!("radius=-1".parse::<NavAgent>().is_err());
+    assert!("width=2".parse::<NavAgent>().is_err());
+    assert!("height".parse::<NavAgent>().is_err());
+    assert!("bidirectional=yes".parse::<NavAgent>().is_err());
+    assert!("jump=far".parse::<NavAgent>().is_err());
+}
+
+#[test]
+fn jumps_over_gaps() {
+    let agent = |text: &str| text.parse::<NavAgent>().unwrap();
+    let settings = CollisionSettings {
+        nav_agents: vec![agent("neighbors=16"), agent("neighbors=16,drop=1"), agent("neighbors=16,jump=0.5")],
+        ..software_settings()
+    };
+    let col = convert_collision(&[platform_model(0.0, 0.0, 4, 0.25), platform_model(1.25, 0.0, 4, 0.25)], &[], &settings).unwrap();
+
+    // Agents that can't drop or jump only need a clear line of sight to walk, like before drops and jumps existed
+    let walks = links_across(&col.nav_graphs[0], 1.125);
+    assert!(!walks.is_empty());
+    assert!(walks.iter().all(|(_, link_type)| *link_type == NavLinkType::Walk));
+
+    // Otherwise the line of sight over the gap is clear, but there's no floor to walk on
+    assert!(links_across(&col.nav_graphs[1], 1.125).is_empty());
+    let jumps = links_across(&col.nav_graphs[2], 1.125);
+    assert!(!jumps.is_empty());
+    assert!(jumps.iter().all(|(_, link_type)| *link_type == NavLinkType::Jump));
+    assert!(jumps.iter().any(|(from_far_side, _)| *from_far_side) && jumps.iter().any(|(from_far_side, _)| !from_far_side));
+}
+
+#[test]
+fn no_jumps_without_a_gap() {
+    // Nodes further apart than the walking distance, but within jumping distance, on one unbroken floor
+    let settings = CollisionSettings {
+        nav_agents: vec!["neighbors=16,distance=0.3,jump=1".parse().unwrap()],
+        ..software_settings()
+    };
+    let col = convert_collision(&[grid_model(8, 0.25)], &[], &settings).unwrap();
+    let graph = &col.nav_graphs[0];
+    assert!(graph.nodes.iter().any(|node| !node.neighbors.is_empty()));
+    assert!(graph.nodes.iter().flat_map(|node| &node.link_types).all(|&link_type| link_type == NavLinkType::Walk));
+}
+
+#[test]
+fn drops_off_ledges() {
+    let agent = |text: &str| text.parse::<NavAgent>().unwrap();
+    let settings = CollisionSettings {
+        nav_agents: vec![agent("neighbors=16"), agent("neighbors=16,drop=1")],
+        ..software_settings()
+    };
+    let ledge = [platform_model(0.0, 0.5, 4, 0.25), wall_model(1.0, 0.5, 1.0), platform_model(1.0, 0.0, 4, 0.25)];
+    let col = convert_collision(&ledge, &[], &settings).unwrap();
+
+    // Drops only go down, from the high side to the low side
+    assert!(links_across(&col.nav_graphs[0], 1.0).is_empty());
+    let drops = links_across(&col.nav_graphs[1], 1.0);
+    assert!(!drops.is_empty());
+    assert!(drops.iter().all(|&link| link == (false, NavLinkType::Drop)));
+
+    round_trip(&col);
+}
+
+#[test]
+fn ladders_from_helper_objects() {
+    let models = [
+        platform_model(0.0, 0.5, 4, 0.25),
+        wall_model(1.0, 0.5, 1.0),
+        platform_model(1.0, 0.0, 4, 0.25),
+        link_model("NAVLINK_LADDER_01", [1.1, 0.0, 0.5], [0.9, 0.5, 0.5]),
+    ];
+    let col = convert_collision(&models, &[], &software_settings()).unwrap();
+
+    // The helper isn't collision, and the ladder goes both ways
+    assert_eq!(col.triangles.len(), 4 * 4 * 2 * 2 + 4);
+    let ladders = links_across(&col.nav_graphs[0], 1.0);
+    assert_eq!(ladders.len(), 2);
+    assert!(ladders.contains(&(true, NavLinkType::Ladder)) && ladders.contains(&(false, NavLinkType::Ladder)));
+
+    let bad_helper = link_model("NAVLINK_TELEPORT", [0.0, 0.0, 0.0], [1.0, 0.0, 0.0]);
+    assert!(convert_collision(&[grid_model(2, 0.5), bad_helper], &[], &software_settings()).is_err());
+}
@@ -0,0 +1,74 @@
+mod common;
+
+use common::{grid_model, round_trip, software_settings};
+use obj2psx::{
+    collision::{convert_collision, CollisionSettings, NavOutput},
+    psx_structs::NAV_NO_NEIGHBOR,
+};
+
+fn navmesh_settings() -> CollisionSettings {
+    CollisionSettings {
+        nav_output: NavOutput::Navmesh,
+        ..software_settings()
+    }
+}
+
+#[test]
+fn merges_floor_into_polygons() {
+    let col = convert_collision(&[grid_model(3, 1.0)], &[], &navmesh_settings()).unwrap();
+    assert!(col.nav_graphs.is_empty());
+    assert!(!col.navmesh_polygons.is_empty());
+    assert!(col.navmesh_polygons.len() < 18);
+
+    for (i, polygon) in col.navmesh_polygons.iter().enumerate() {
+        assert!((3..=6).contains(&polygon.vertices.len()));
+        assert_eq!(polygon.vertices.len(), polygon.neighbors.len());
+
+        // Every portal has to lead back through the same edge
+        for (edge, &neighbor) in polygon.neighbors.iter().enumerate() {
+            if neighbor == NAV_NO_NEIGHBOR {
+                continue;
+            }
+            let a = polygon.vertices[edge];
+            let b = polygon.vertices[(edge + 1) % polygon.vertices.len()];
+            let other = &col.navmesh_polygons[neighbor as usize];
+            let back = (0..other.vertices.len())
+                .find(|&j| other.vertices[j] == b && other.vertices[(j + 1) % other.vertices.len()] == a)
+                .expect("portal edge is missing on the other side");
+            assert_eq!(other.neighbors[back], i as u32);
+        }
+    }
+}
+
+#[test]
+fn navmesh_round_trip() {
+    let col = convert_collision(&[grid_model(2, 1.0)], &[], &navmesh_settings()).unwrap();
+    round_trip(&col);
+}
+
+#[test]
+fn keeps_slopes_apart() {
+    // A flat quad from x = 0 to 1, and a ramp from x = 1 to 2 going up by 0.25. Seen from above they form one convex rectangle
+    let mut mesh = tobj::Mesh::default();
+    for z in 0..=1 {
+        for (x, y) in [(0.0, 0.0), (1.0, 0.0), (2.0, 0.25)] {
+            mesh.positions.extend_from_slice(&[x, y, z as f32]);
+        }
+    }
+    let index = |x: u32, z: u32| z * 3 + x;
+    for x in 0..2 {
+        mesh.indices.extend_from_slice(&[index(x, 0), index(x + 1, 1), index(x + 1, 0)]);
+        mesh.indices.extend_from_slice(&[index(x, 0), index(x, 1), index(x + 1, 1)]);
+    }
+    let col = convert_collision(&[tobj::Model::new(mesh, "ramp".to_string())], &[], &navmesh_settings()).unwrap();
+    assert_eq!(col.navmesh_polygons.len(), 2);
+
+    // Every polygon has to lie in a single plane, so its height can be found from any three of its vertices
+    for polygon in &col.navmesh_polygons {
+        let position = |i: usize| col.navmesh_vertices[polygon.vertices[i] as usize].as_dvec3();
+        let normal = (position(1) - position(0)).cross(position(2) - position(0)).normalize();
+        for i in 3..polygon.vertices.len() {
+            assert!(normal.dot(position(i) - position(0)).abs() <= 512.0);
+        }
+    }
+}
@@ -0,0 +1,34 @@
+use obj2psx::{psx_structs::CollVertexPSX, renderer::SoftwareRenderer};
+
+/// A square wall across the X axis at `x`, with both windings so it blocks from either side
+fn wall(x: i16) -> Vec<CollVertexPSX> {
+    let vertex = |y: i16, z: i16| CollVertexPSX {
+        pos_x: x,
+        pos_y: y,
+        pos_z: z,
+        terrain_id: 0,
+    };
+    let [a, b, c, d] = [vertex(-50, -50), vertex(50, -50), vertex(50, 50), vertex(-50, 50)];
+    vec![a, b, c, a, c, d, a, c, b, a, d, c]
+}
+
+fn is_occupied(wall_x: i16) -> bool {
+    let mut renderer = SoftwareRenderer::new();
+    renderer.upload_mesh(&wall(wall_x));
+
+    // The renderers take positions with the sign flipped, like the nav graph passes them
+    let from = -glam::vec3(-100.0, 0.0, 0.0);
+    let to = -glam::vec3(100.0, 0.0, 0.0);
+    renderer.is_path_occupied(from, to, 10.0)
+}
+
+#[test]
+fn only_walls_between_the_points_block() {
+    assert!(is_occupied(0));
+    assert!(is_occupied(-90));
+    assert!(is_occupied(90));
+
+    // Behind the source or past the target
+    assert!(!is_occupied(-150));
+    assert!(!is_occupied(150));
+}
@@ -0,0 +1,295 @@
+mod common;
+
+use common::{floor_model, grid_model, round_trip, software_settings, vertex};
+use obj2psx::{
+    collision::{convert_collision, BvhBuilder, CollisionSettings, SurfaceType},
+    psx_structs::{CollHeader, CollModelPSX, FCOL_VERSION, MeshPSX, ModelPSX, TextureCellPSX, TextureCollectionPSX},
+    Error,
+};
+
+#[test]
+fn msh_round_trip() {
+    let model = ModelPSX {
+        meshes: vec![
+            MeshPSX {
+                verts: (0..3).map(vertex).collect(),
+                n_triangles: 1,
+                n_quads: 0,
+                name: "triangle".to_string(),
+            },
+            MeshPSX {
+                verts: (3..14).map(vertex).collect(),
+                n_triangles: 1,
+                n_quads: 2,
+                name: "quads".to_string(),
+            },
+        ],
+    };
+
+    let mut data = Vec::new();
+    model.write(&mut data).unwrap();
+    assert_eq!(ModelPSX::read(&data).unwrap(), model);
+
+    let descs = ModelPSX::read_mesh_descs(&data).unwrap();
+    assert_eq!(descs, model.mesh_descs().unwrap());
+    assert_eq!(descs[1].vertex_start, 3);
+}
+
+#[test]
+fn txc_round_trip() {
+    let txc = TextureCollectionPSX {
+        texture_cells: vec![
+            TextureCellPSX {
+                texture_data: (0..32).collect(),
+                palette: (0..256).map(|i| i * 3).collect(),
+                texture_width: 8,
+                texture_height: 8,
+                avg_color: 0x80FF8040,
+                texture_bpp: 4,
+            },
+            TextureCellPSX {
+                texture_data: (0..64).map(|i| 255 - i).collect(),
+                palette: (0..4096).map(|i| i as u16).collect(),
+                texture_width: 16,
+                texture_height: 4,
+                avg_color: 0,
+                texture_bpp: 8,
+            },
+            TextureCellPSX {
+                texture_data: (0..32).map(|i| i * 5).collect(),
+                palette: vec![],
+                texture_width: 4,
+                texture_height: 4,
+                avg_color: 0x12345678,
+                texture_bpp: 15,
+            },
+        ],
+        texture_names: vec!["brick.png".to_string(), "hero_8bpp.png".to_string(), "sky_15bpp.png".to_string()],
+    };
+
+    let mut data = Vec::new();
+    txc.write(&mut data).unwrap();
+    let read_back = TextureCollectionPSX::read(&data).unwrap();
+    assert_eq!(read_back, txc);
+    assert_eq!(read_back.texture_id("hero_8bpp.png"), Some(1));
+    assert_eq!(read_back.texture_name(2), Some("sky_15bpp.png"));
+}
+
+/// Builds a texture collection with one 16x16 4bpp texture, laid out the way the converter wrote them before the name table
+fn txc_without_name_table() -> Vec<u8> {
+    let palette: Vec<u8> = (0..16u16).flat_map(|color| (color * 0x421).to_le_bytes()).collect();
+    let texture: Vec<u8> = (0..128).map(|i| i as u8).collect();
+    let cell_desc = [0, 0, 16, 16, 4, 1, 0, 0, 0x40, 0x30, 0x20, 0x10];
+
+    // Texture data was padded as if the header was 24 bytes, so it ended up 4 bytes after a sector boundary
+    let offset_palettes = cell_desc.len() + 4;
+    let cursor = offset_palettes + palette.len();
+    let padding = (cursor + 24).next_multiple_of(2048) - (cursor + 24);
+
+    let mut data = Vec::new();
+    data.extend_from_slice(b"FTXC");
+    for value in [1, 0, cell_desc.len(), offset_palettes, cursor + padding, 0] {
+        data.extend_from_slice(&(value as u32).to_le_bytes());
+    }
+    data.extend_from_slice(&cell_desc);
+    data.extend_from_slice(&0u32.to_le_bytes());
+    data.extend_from_slice(&palette);
+    data.resize(data.len() + padding, 0);
+    data.extend_from_slice(&texture);
+    data
+}
+
+#[test]
+fn reads_txc_without_name_table() {
+    let txc = TextureCollectionPSX::read(&txc_without_name_table()).unwrap();
+    assert_eq!(txc.texture_cells.len(), 1);
+    assert!(txc.texture_names.is_empty());
+    assert_eq!(txc.texture_name(0), None);
+
+    let cell = &txc.texture_cells[0];
+    assert_eq!((cell.texture_width, cell.texture_height, cell.texture_bpp), (16, 16, 4));
+    assert_eq!(cell.avg_color, 0x10203040);
+    assert_eq!(cell.palette[15], 15 * 0x421);
+    assert_eq!(cell.texture_data, (0..128).map(|i| i as u8).collect::<Vec<_>>());
+
+    // Other misaligned texture data is still rejected
+    let mut data = txc_without_name_table();
+    data[20..24].copy_from_slice(&(2042u32).to_le_bytes());
+    assert!(matches!(TextureCollectionPSX::read(&data), Err(Error::Misaligned { .. })));
+}
+
+#[test]
+fn col_round_trip() {
+    let settings = software_settings();
+    let col = convert_collision(&[floor_model()], &[], &settings).unwrap();
+    assert_eq!(col.triangles.len(), 3);
+
+    round_trip(&col);
+}
+
+#[test]
+fn sah_bvh_round_trip() {
+    let settings = CollisionSettings {
+        bvh_builder: BvhBuilder::Sah,
+        ..software_settings()
+    };
+    let col = convert_collision(&[floor_model()], &[], &settings).unwrap();
+
+    // Every triangle should be referenced exactly once
+    let mut indices = col.indices.clone();
+    indices.sort();
+    assert_eq!(indices, (0..col.triangles.len() as u32).collect::<Vec<_>>());
+
+    round_trip(&col);
+}
+
+#[test]
+fn mean_bvh_matches_original_splitter() {
+    // The grid is as wide as it is deep, so no axis is the longest and the root is split on X at 0. Every triangle is on
+    // the positive side, except the first one, which the original partition leaves on the back side
+    let col = convert_collision(&[grid_model(2, 1.0)], &[], &software_settings()).unwrap();
+    let root = &col.nodes[0];
+    let [front, back] = [&col.nodes[root.left_first as usize], &col.nodes[root.left_first as usize + 1]];
+    assert_eq!(back.primitive_count, 1);
+    assert_eq!(col.indices[back.left_first as usize], 0);
+    assert_eq!(front.primitive_count, 0);
+}
+
+#[test]
+fn bvh_splits_triangles_with_the_same_center() {
+    // Triangles of growing size around the origin, which all have the center of their bounds at the origin
+    let mut mesh = tobj::Mesh::default();
+    for k in 1..=8 {
+        let k = k as f32;
+        mesh.positions.extend_from_slice(&[-k, 0.0, -k, 0.0, 0.0, k, k, 0.0, -k]);
+    }
+    mesh.indices = (0..mesh.positions.len() as u32 / 3).collect();
+    let model = tobj::Model::new(mesh, "nested".to_string());
+
+    for bvh_builder in [BvhBuilder::Mean, BvhBuilder::Sah] {
+        let settings = CollisionSettings {
+            bvh_builder,
+            ..software_settings()
+        };
+        let col = convert_collision(std::slice::from_ref(&model), &[], &settings).unwrap();
+        assert_eq!(col.triangles.len(), 8);
+        // The root's children start at node 2, after an unused dummy node
+        assert_eq!(col.nodes[0].primitive_count, 0);
+        assert!(col.nodes[2..].iter().all(|node| node.primitive_count <= settings.max_leaf_size as _));
+    }
+}
+
+#[test]
+fn bvh_respects_depth_limit() {
+    let settings = CollisionSettings {
+        max_leaf_size: 1,
+        max_bvh_depth: 5,
+        bvh_stack_size: 2,
+        ..software_settings()
+    };
+    let col = convert_collision(&[floor_model()], &[], &settings).unwrap();
+
+    // A stack of 2 entries only allows a depth of 1, so the root's children have to be leaves
+    let root = &col.nodes[0];
+    assert_eq!(root.primitive_count, 0);
+    for child in &col.nodes[root.left_first as usize..root.left_first as usize + 2] {
+        assert!(child.primitive_count > 0);
+    }
+}
+
+#[test]
+fn wide_index_round_trip() {
+    let settings = CollisionSettings {
+        wide_indices: true,
+        ..software_settings()
+    };
+    let col = convert_collision(&[floor_model()], &[], &settings).unwrap();
+    assert!(col.wide_indices);
+
+    let data = round_trip(&col);
+
+    // The same model with 16-bit indices should be smaller
+    let narrow = CollModelPSX {
+        wide_indices: false,
+        ..col
+    };
+    assert!(round_trip(&narrow).len() < data.len());
+}
+
+#[test]
+fn classifies_surfaces_by_slope() {
+    // The third triangle of the floor model faces down, at about 63 degrees from vertical
+    let col = convert_collision(&[floor_model()], &[], &software_settings()).unwrap();
+    let surface_types: Vec<_> = col.triangles.iter().map(|triangle| triangle.surface_type).collect();
+    assert_eq!(surface_types, [SurfaceType::Floor, SurfaceType::Floor, SurfaceType::Wall]);
+
+    let settings = CollisionSettings {
+        slope_limit: 70.0,
+        ..software_settings()
+    };
+    let col = convert_collision(&[floor_model()], &[], &settings).unwrap();
+    assert_eq!(col.triangles[2].surface_type, SurfaceType::Ceiling);
+
+    assert_eq!(SurfaceType::classify(glam::IVec3::new(0, 2048, 3547), 60.0), SurfaceType::Floor);
+}
+
+#[test]
+fn terrain_ids_from_materials() {
+    let mut ice = tobj::Material {
+        name: "ice".to_string(),
+        ..Default::default()
+    };
+    ice.unknown_param.insert("psx_terrain".to_string(), "3".to_string());
+    let mut model = floor_model();
+    model.mesh.material_id = Some(0);
+
+    // The MTL parameter is used when the material isn't in the terrain map
+    let mut settings = software_settings();
+    let col = convert_collision(&[model.clone()], &[ice.clone()], &settings).unwrap();
+    assert!(col.triangles.iter().all(|triangle| triangle.terrain_id == 3));
+
+    // The terrain map takes priority over the MTL parameter
+    settings.terrain_ids.insert("ice".to_string(), 7);
+    let col = convert_collision(&[model], &[ice], &settings).unwrap();
+    assert!(col.triangles.iter().all(|triangle| triangle.terrain_id == 7));
+
+    round_trip(&col);
+}
+
+#[test]
+fn rejects_wrong_magic() {
+    let mut data = Vec::new();
+    ModelPSX::default().write(&mut data).unwrap();
+    assert!(matches!(TextureCollectionPSX::read(&data), Err(Error::BadMagic { .. })));
+    assert!(matches!(CollModelPSX::read(&data), Err(Error::BadMagic { .. })));
+}
+
+#[test]
+fn rejects_truncated_file() {
+    let settings = software_settings();
+    let col = convert_collision(&[floor_model()], &[], &settings).unwrap();
+    let mut data = Vec::new();
+    col.write(&mut data).unwrap();
+    data.truncate(data.len() - 1);
+    assert!(matches!(CollModelPSX::read(&data), Err(Error::OutOfBounds { .. })));
+}
+
+#[test]
+fn col_header_matches_contents() {
+    let settings = software_settings();
+    let col = convert_collision(&[floor_model()], &[], &settings).unwrap();
+    let mut data = Vec::new();
+    col.write(&mut data).unwrap();
+
+    let header = CollHeader::read(&data).unwrap();
+    assert_eq!(header.version, FCOL_VERSION);
+    assert_eq!(header.n_triangles, col.triangles.len());
+    assert_eq!(header.n_nodes, col.nodes.len());
+    assert_eq!(header.n_indices, col.indices.len());
+    assert_eq!(header.n_nav_graphs, col.nav_graphs.len());
+    header.verify(data.len()).unwrap();
+
+    // Move the BVH node offset (the 9th header field) off its alignment
+    data[36] += 2;
+    assert!(matches!(CollModelPSX::read(&data), Err(Error::Misaligned { .. })));
+}
@@ -0,0 +1,124 @@
+mod common;
+
+use common::{round_trip, software_settings};
+use obj2psx::collision::{convert_collision, CollShape};
+
+/// A box from `min` to `max` made of 12 triangles
+fn box_model(name: &str, min: [f32; 3], max: [f32; 3]) -> tobj::Model {
+    let mut mesh = tobj::Mesh::default();
+    for corner in 0..8 {
+        for axis in 0..3 {
+            mesh.positions.push(if corner & (1 << axis) != 0 { max[axis] } else { min[axis] });
+        }
+    }
+    for [a, b, c, d] in [[0, 1, 3, 2], [4, 6, 7, 5], [0, 4, 5, 1], [2, 3, 7, 6], [0, 2, 6, 4], [1, 5, 7, 3]] {
+        mesh.indices.extend_from_slice(&[a, b, c, a, c, d]);
+    }
+    tobj::Model::new(mesh, name.to_string())
+}
+
+/// Converts an OBJ position to the space collision triangles and shapes are stored in
+fn to_collision(position: [f32; 3]) -> glam::DVec3 {
+    glam::dvec3(position[0] as f64, position[1] as f64, -position[2] as f64) * 512.0 * 1024.0
+}
+
+fn corners(min: [f32; 3], max: [f32; 3]) -> Vec<glam::DVec3> {
+    (0..8)
+        .map(|corner| to_collision(std::array::from_fn(|axis| if corner & (1 << axis) != 0 { max[axis] } else { min[axis] })))
+        .collect()
+}
+
+fn distance_to_segment(point: glam::DVec3, a: glam::DVec3, b: glam::DVec3) -> f64 {
+    let t = ((point - a).dot(b - a) / (b - a).length_squared().max(1.0)).clamp(0.0, 1.0);
+    point.distance(a + (b - a) * t)
+}
+
+const BOX: ([f32; 3], [f32; 3]) = ([0.5, 0.0, 0.5], [0.75, 0.25, 1.0]);
+const SPHERE: ([f32; 3], [f32; 3]) = ([-1.0, 0.0, -1.0], [-0.5, 0.5, -0.5]);
+const CAPSULE: ([f32; 3], [f32; 3]) = ([1.0, 0.0, -1.0], [1.25, 1.0, -0.75]);
+
+fn shapes_model() -> Vec<tobj::Model> {
+    vec![
+        box_model("floor", [-2.0, -0.25, -2.0], [2.0, 0.0, 2.0]),
+        box_model("BOX_CRATE", BOX.0, BOX.1),
+        box_model("SPHERE_BALL", SPHERE.0, SPHERE.1),
+        box_model("CAPSULE_PILLAR", CAPSULE.0, CAPSULE.1),
+    ]
+}
+
+#[test]
+fn fits_shapes_around_objects() {
+    let col = convert_collision(&shapes_model(), &[], &software_settings()).unwrap();
+
+    // Only the floor is made of triangles
+    assert_eq!(col.triangles.len(), 12);
+    assert_eq!(col.shapes.len(), 3);
+
+    let CollShape::Box { min, max } = col.shapes[0].shape else {
+        panic!("expected a box, got {:?}", col.shapes[0].shape);
+    };
+    let corners_box = corners(BOX.0, BOX.1);
+    let expected_min = corners_box.iter().fold(glam::DVec3::MAX, |a, b| a.min(*b));
+    let expected_max = corners_box.iter().fold(glam::DVec3::MIN, |a, b| a.max(*b));
+    assert_eq!((min.as_dvec3(), max.as_dvec3()), (expected_min, expected_max));
+
+    let CollShape::Sphere { center, radius } = col.shapes[1].shape else {
+        panic!("expected a sphere, got {:?}", col.shapes[1].shape);
+    };
+    let half_diagonal = corners(SPHERE.0, SPHERE.1)[0].distance(corners(SPHERE.0, SPHERE.1)[7]) / 2.0;
+    for corner in corners(SPHERE.0, SPHERE.1) {
+        assert!(corner.distance(center.as_dvec3()) <= radius as f64);
+    }
+    assert!(radius as f64 <= half_diagonal + 2.0);
+
+    // The pillar is 4 times as tall as it is wide, so the capsule should stand up
+    let CollShape::Capsule { a, b, radius } = col.shapes[2].shape else {
+        panic!("expected a capsule, got {:?}", col.shapes[2].shape);
+    };
+    let (a, b) = (a.as_dvec3(), b.as_dvec3());
+    assert_eq!((a.x, a.z), (b.x, b.z));
+    assert!((a.y - b.y).abs() > 0.0);
+    for corner in corners(CAPSULE.0, CAPSULE.1) {
+        assert!(distance_to_segment(corner, a, b) <= radius as f64);
+    }
+    let half_width_diagonal = 0.125 * 2f64.sqrt() * 512.0 * 1024.0;
+    assert!(radius as f64 <= half_width_diagonal + 2.0);
+}
+
+#[test]
+fn bvh_contains_triangles_and_shapes() {
+    let col = convert_collision(&shapes_model(), &[], &software_settings()).unwrap();
+    let mut indices = col.indices.clone();
+    indices.sort();
+    assert_eq!(indices, (0..col.n_primitives() as u32).collect::<Vec<_>>());
+
+    // Every leaf's bounds contain its shapes. Node 1 is padding, so we walk the tree from the root
+    let mut n_shapes_found = 0;
+    let mut stack = vec![0usize];
+    while let Some(node_index) = stack.pop() {
+        let node = &col.nodes[node_index];
+        if node.primitive_count == 0 {
+            stack.extend([node.left_first as usize, node.left_first as usize + 1]);
+            continue;
+        }
+        for &index in &col.indices[node.left_first as usize..(node.left_first + node.primitive_count) as usize] {
+            let Some(shape) = (index as usize).checked_sub(col.triangles.len()).map(|i| &col.shapes[i]) else {
+                continue;
+            };
+            let bounds = shape.shape.bounds();
+            assert!(bounds.min.cmpge(node.bounds.min).all() && bounds.max.cmple(node.bounds.max).all());
+            n_shapes_found += 1;
+        }
+    }
+    assert_eq!(n_shapes_found, col.shapes.len());
+}
+
+#[test]
+fn shapes_round_trip() {
+    let col = convert_collision(&shapes_model(), &[], &software_settings()).unwrap();
+    round_trip(&col);
+
+    // An empty shape object has nothing to fit the shape around
+    let empty = tobj::Model::new(tobj::Mesh::default(), "SPHERE_EMPTY".to_string());
+    assert!(convert_collision(&[box_model("floor", [0.0; 3], [1.0; 3]), empty], &[], &software_settings()).is_err());
+}
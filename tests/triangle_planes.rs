@@ -0,0 +1,67 @@
+mod common;
+
+use common::{floor_model, round_trip, software_settings};
+use obj2psx::{
+    collision::{convert_collision, CollisionSettings},
+    psx_structs::{CollHeader, CollModelPSX},
+};
+
+fn convert(precomputed_planes: bool) -> CollModelPSX {
+    let settings = CollisionSettings {
+        precomputed_planes,
+        ..software_settings()
+    };
+    convert_collision(&[floor_model()], &[], &settings).unwrap()
+}
+
+/// Signed distance from a plane in fixed point to a point, in vertex units
+fn distance(normal: glam::IVec3, distance: i32, point: glam::IVec3) -> i64 {
+    normal.as_i64vec3().dot(point.as_i64vec3()) / 4096 - distance as i64
+}
+
+#[test]
+fn planes_match_the_triangles() {
+    let col = convert(false);
+    for triangle in &col.triangles {
+        let planes = triangle.planes();
+        let vertices = [triangle.v0, triangle.v1, triangle.v2];
+
+        // Rounding the normals to fixed point moves the planes a little, relative to the size of the triangles
+        let tolerance = 512 * 1024 / 1000;
+        for vertex in vertices {
+            assert!(distance(triangle.normal, planes.distance, vertex).abs() < tolerance);
+        }
+        for i in 0..3 {
+            let (normal, edge_distance) = (planes.edge_normals[i], planes.edge_distances[i]);
+            assert!(normal.as_vec3().dot(triangle.normal.as_vec3()).abs() < 4096.0 * 4096.0 / 1000.0);
+            assert!(distance(normal, edge_distance, vertices[i]).abs() < tolerance);
+            assert!(distance(normal, edge_distance, vertices[(i + 1) % 3]).abs() < tolerance);
+            assert!(distance(normal, edge_distance, vertices[(i + 2) % 3]) > tolerance);
+        }
+    }
+}
+
+#[test]
+fn precomputed_planes_round_trip() {
+    let col = convert(true);
+    assert!(col.precomputed_planes);
+    let data = round_trip(&col);
+
+    // The section follows the layout of `CollTrianglePSX::planes`
+    let header = CollHeader::read(&data).unwrap();
+    assert!(header.precomputed_planes());
+    let read_i32 = |offset: usize| i32::from_le_bytes(data[offset..offset + 4].try_into().unwrap());
+    for (i, triangle) in col.triangles.iter().enumerate() {
+        let planes = triangle.planes();
+        let offset = header.triangle_planes_offset + i * 52;
+        assert_eq!(read_i32(offset), planes.distance);
+        for edge in 0..3 {
+            let offset = offset + 4 + edge * 16;
+            let normal = glam::IVec3::new(read_i32(offset), read_i32(offset + 4), read_i32(offset + 8));
+            assert_eq!((normal, read_i32(offset + 12)), (planes.edge_normals[edge], planes.edge_distances[edge]));
+        }
+    }
+
+    // Without the flag, the section is left out
+    assert_eq!(data.len() - round_trip(&convert(false)).len(), col.triangles.len() * 52);
+}
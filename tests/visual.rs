@@ -0,0 +1,106 @@
+use std::path::Path;
+
+use obj2psx::{convert_visual, VisualSettings};
+
+fn material(name: &str, texture: &str) -> tobj::Material {
+    tobj::Material {
+        name: name.to_string(),
+        diffuse_texture: Some(texture.to_string()),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn texture_names_keep_their_directory() {
+    // The textures don't exist, so they become blank textures, but they keep their names
+    let materials = [
+        material("brick", "levels/wall.png"),
+        material("metal", "props\\wall.png"),
+        material("brick_again", "./levels/wall.png"),
+    ];
+    let (_, txc) = convert_visual(&[], &materials, Path::new("missing"), &VisualSettings::default()).unwrap();
+    assert_eq!(txc.texture_names, ["levels/wall.png", "props/wall.png"]);
+    assert_eq!(txc.texture_id("props/wall.png"), Some(1));
+}
+
+fn bit_depths(materials: &[tobj::Material], settings: &VisualSettings) -> Vec<i32> {
+    let (_, txc) = convert_visual(&[], materials, Path::new("missing"), settings).unwrap();
+    txc.texture_cells.iter().map(|cell| cell.texture_bpp).collect()
+}
+
+#[test]
+fn bit_depth_from_file_name() {
+    let materials = [
+        material("a", "a_4bpp.png"),
+        material("b", "b_8bpp.png"),
+        material("c", "c_15bpp.png"),
+        material("d", "d_16bpp.png"),
+        material("e", "e.png"),
+    ];
+    assert_eq!(bit_depths(&materials, &VisualSettings::default()), [4, 8, 15, 15, 4]);
+
+    // Only textures without a suffix follow the texture page setting
+    let settings = VisualSettings {
+        using_texture_page: true,
+        ..Default::default()
+    };
+    assert_eq!(bit_depths(&materials, &settings), [4, 8, 15, 15, 8]);
+}
+
+#[test]
+fn bit_depth_from_material() {
+    let with_bpp = |name: &str, texture: &str, bpp: &str| tobj::Material {
+        unknown_param: [("psx_bpp".to_string(), bpp.to_string())].into(),
+        ..material(name, texture)
+    };
+
+    // The material parameter wins over the file name
+    let materials = [with_bpp("a", "a_4bpp.png", "8"), with_bpp("b", "b.png", " 15 "), with_bpp("c", "c_15bpp.png", "4")];
+    assert_eq!(bit_depths(&materials, &VisualSettings::default()), [8, 15, 4]);
+
+    let materials = [with_bpp("bad", "a.png", "3")];
+    assert!(convert_visual(&[], &materials, Path::new("missing"), &VisualSettings::default()).is_err());
+}
+
+#[test]
+fn packs_15bpp_pixels() {
+    // An uncompressed 32-bit TGA with red, green, transparent blue and opaque black pixels, stored as BGRA from the top left
+    let header = vec![0u8, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 0, 2, 0, 32, 0x28];
+    let pixels = [[0, 0, 255, 255], [0, 255, 0, 255], [255, 0, 0, 0], [0, 0, 0, 255]];
+
+    let dir = std::env::temp_dir().join(format!("obj2psx_visual_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("colors_15bpp.tga"), [header, pixels.concat()].concat()).unwrap();
+    let result = convert_visual(&[], &[material("colors", "colors_15bpp.tga")], &dir, &VisualSettings::default());
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let (_, txc) = result.unwrap();
+    let cell = &txc.texture_cells[0];
+    assert!(cell.palette.is_empty());
+    let colors: Vec<u16> = cell.texture_data.chunks_exact(2).map(|color| u16::from_le_bytes([color[0], color[1]])).collect();
+
+    // Opaque pixels have the STP bit set, so opaque black isn't transparent like a fully transparent pixel
+    assert_eq!(colors, [0x801F, 0x83E0, 0x0000, 0x8000]);
+}
+
+#[test]
+fn opaque_textures_have_opaque_average_color() {
+    // A blank texture is used when the file is missing, which is opaque white
+    let materials = [material("missing", "missing.png")];
+    let (_, txc) = convert_visual(&[], &materials, Path::new("missing"), &VisualSettings::default()).unwrap();
+    assert_eq!(txc.texture_cells[0].avg_color, 0xFFFFFFFF);
+
+    // An uncompressed 24-bit TGA with a red and a blue pixel, stored as BGR from the top left
+    let header = vec![0u8, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 0, 1, 0, 24, 0x20];
+    let pixels = [[0, 0, 255], [255, 0, 0]];
+
+    let dir = std::env::temp_dir().join(format!("obj2psx_avg_color_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("colors.tga"), [header, pixels.concat()].concat()).unwrap();
+    let result = convert_visual(&[], &[material("colors", "colors.tga")], &dir, &VisualSettings::default());
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    // Stored as red, blue, green and alpha from the lowest byte up
+    let (_, txc) = result.unwrap();
+    assert_eq!(txc.texture_cells[0].avg_color, 0xFF007F7F);
+}
@@ -0,0 +1,67 @@
+use obj2psx::{
+    psx_structs::CollVertexPSX,
+    weld::{weld, WeldReport},
+};
+
+fn vertex(pos_x: i16, pos_y: i16, pos_z: i16) -> CollVertexPSX {
+    CollVertexPSX {
+        pos_x,
+        pos_y,
+        pos_z,
+        terrain_id: 0,
+    }
+}
+
+/// Two quads next to each other, with a gap of `gap` units between them
+fn two_quads(gap: i16) -> Vec<CollVertexPSX> {
+    let quad = |x: i16| {
+        [
+            vertex(x, 0, 0),
+            vertex(x + 1024, 0, 0),
+            vertex(x + 1024, 0, 1024),
+            vertex(x, 0, 0),
+            vertex(x + 1024, 0, 1024),
+            vertex(x, 0, 1024),
+        ]
+    };
+    quad(0).into_iter().chain(quad(1024 + gap)).collect()
+}
+
+#[test]
+fn closes_cracks_within_tolerance() {
+    let mut report = WeldReport::default();
+    let mut triangles = two_quads(2);
+    weld(&mut triangles, 0, &mut report);
+    assert_eq!(report.n_welded_vertices, 0);
+    assert_eq!(report.open_edges.len(), 8);
+
+    let mut report = WeldReport::default();
+    let mut triangles = two_quads(2);
+    weld(&mut triangles, 2, &mut report);
+    assert_eq!(report.n_welded_vertices, 3);
+    assert_eq!(report.open_edges.len(), 6);
+    assert_eq!(triangles.len(), 12);
+}
+
+#[test]
+fn removes_degenerate_triangles() {
+    let mut report = WeldReport::default();
+    let mut triangles = vec![
+        // Two vertices in the same place
+        vertex(0, 0, 0),
+        vertex(0, 0, 0),
+        vertex(100, 0, 0),
+        // Three vertices on a line
+        vertex(0, 0, 0),
+        vertex(50, 0, 0),
+        vertex(100, 0, 0),
+        // Two vertices that end up in the same place after welding
+        vertex(0, 0, 0),
+        vertex(1, 0, 0),
+        vertex(0, 0, 100),
+    ];
+    weld(&mut triangles, 1, &mut report);
+    assert!(triangles.is_empty());
+    assert_eq!(report.n_degenerate_triangles, 2);
+    assert_eq!(report.n_zero_normal_triangles, 1);
+}